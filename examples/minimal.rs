@@ -41,7 +41,7 @@ fn main() {
                 ],
                 plugin_paths: Some(&["./phonon_fmod.dll"]),
             },
-            PhononPlugin,
+            PhononPlugin::default(),
         ))
         .add_plugins(LookTransformPlugin)
         .add_plugins(FpsCameraPlugin::default())
@@ -85,7 +85,7 @@ fn setup_scene(
                             .with_translation(Vec3::new(x as f32, y as f32, z as f32)),
                         ..default()
                     },
-                    NeedsAudioMesh(materials::BRICK),
+                    NeedsAudioMesh::new(materials::BRICK),
                     TorusMarker,
                 ));
             }
@@ -100,7 +100,7 @@ fn setup_scene(
                 .with_translation(Vec3::new(7.0, 0.0, 0.0)),
             ..default()
         },
-        NeedsAudioMesh(materials::METAL),
+        NeedsAudioMesh::new(materials::METAL),
         TorusMarker,
     ));
 
@@ -139,6 +139,7 @@ fn setup_scene(
 
     commands
         .spawn(SpatialAudioBundle::new(event_description))
+        .insert(PhononSourceSettings::default())
         .insert(PbrBundle {
             mesh: meshes.add(Cuboid::default()),
             material: materials.add(Color::rgb(0.8, 0.2, 0.2)),
@@ -0,0 +1,169 @@
+use crate::phonon_mesh::mesh::AudioMeshError;
+#[cfg(feature = "fmod")]
+use crate::phonon_parameters::SimulationOutput;
+use crate::settings::SettingsError;
+#[cfg(feature = "fmod")]
+use crate::spatializer::SpatializerError;
+use bevy::prelude::*;
+use std::fmt;
+
+/// Everything that can go wrong in this crate.
+/// Errors during startup end up in `PhononStatus`, errors afterwards are sent as events.
+/// So are startup errors that only disable a part of the crate, they arrive in the first frame.
+/// Steam Audio errors are kept as their debug representation.
+#[derive(Event, Debug)]
+pub enum PhononError {
+    InvalidSettings(SettingsError),
+    /// `PhononPlugin` was added before `FmodPlugin`.
+    #[cfg(feature = "fmod")]
+    MissingFmodPlugin,
+    /// `PhononPlugin` was added before `AssetPlugin`, which material assets need.
+    MissingAssetPlugin,
+    /// The audio format could not be read from the FMOD core system.
+    #[cfg(feature = "fmod")]
+    FmodSystem(libfmod::Error),
+    ContextCreation(String),
+    HrtfCreation(String),
+    SceneCreation(String),
+    SimulatorCreation(String),
+    /// `PhononQueries` are unavailable, the rest of the simulation works.
+    QuerySimulatorCreation(String),
+    /// `SimulationExecution::Background` could not start its thread.
+    SimulationThreadSpawn(String),
+    SourceCreation {
+        entity: Entity,
+        error: String,
+    },
+    MeshCreation {
+        entity: Entity,
+        error: String,
+    },
+    /// The Bevy mesh of an entity with `NeedsAudioMesh` could not be converted.
+    AudioMesh {
+        entity: Entity,
+        error: AudioMeshError,
+    },
+    /// The Bevy mesh asset of an entity with `NeedsAudioMesh` does not exist.
+    MeshAssetNotFound(Entity),
+    /// The Bevy mesh asset of an entity with `NeedsAudioMesh` failed to load.
+    MeshLoadFailed(Entity),
+    /// The `PhononMaterial` asset of an entity with `NeedsAudioMesh` failed to load.
+    MaterialLoadFailed(Entity),
+    /// The Steam Audio Spatializer DSPs of an audio source could not be found or set.
+    #[cfg(feature = "fmod")]
+    Spatializer {
+        entity: Entity,
+        error: SpatializerError,
+    },
+    /// A `PhononParameterBinding` could not set its FMOD parameter. It is tried again
+    /// periodically, the error is sent again only after the parameter has been set.
+    #[cfg(feature = "fmod")]
+    ParameterBinding {
+        entity: Entity,
+        parameter: String,
+        error: libfmod::Error,
+    },
+    /// A `PhononParameterBinding` reads a band other than 0, 1 or 2, the mapping is skipped.
+    #[cfg(feature = "fmod")]
+    InvalidParameterBand {
+        entity: Entity,
+        parameter: String,
+        output: SimulationOutput,
+    },
+}
+
+impl fmt::Display for PhononError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PhononError::InvalidSettings(error) => write!(f, "invalid settings: {error}"),
+            #[cfg(feature = "fmod")]
+            PhononError::MissingFmodPlugin => {
+                write!(f, "PhononPlugin requires FmodPlugin to be added first")
+            }
+            PhononError::MissingAssetPlugin => {
+                write!(f, "PhononPlugin requires AssetPlugin to be added first")
+            }
+            #[cfg(feature = "fmod")]
+            PhononError::FmodSystem(error) => write!(f, "FMOD error: {error:?}"),
+            PhononError::ContextCreation(error) => {
+                write!(f, "could not create the Steam Audio context: {error}")
+            }
+            PhononError::HrtfCreation(error) => write!(f, "could not load the HRTF: {error}"),
+            PhononError::SceneCreation(error) => write!(f, "could not create a scene: {error}"),
+            PhononError::SimulatorCreation(error) => {
+                write!(f, "could not create the simulator: {error}")
+            }
+            PhononError::QuerySimulatorCreation(error) => {
+                write!(
+                    f,
+                    "could not create the simulator of PhononQueries: {error}"
+                )
+            }
+            PhononError::SimulationThreadSpawn(error) => {
+                write!(f, "could not start the simulation thread: {error}")
+            }
+            PhononError::SourceCreation { entity, error } => {
+                write!(f, "could not create a source for {entity:?}: {error}")
+            }
+            PhononError::MeshCreation { entity, error } => {
+                write!(f, "could not create audio geometry for {entity:?}: {error}")
+            }
+            PhononError::AudioMesh { entity, error } => {
+                write!(f, "could not convert the mesh of {entity:?}: {error:?}")
+            }
+            PhononError::MeshAssetNotFound(entity) => {
+                write!(f, "the mesh of {entity:?} does not exist")
+            }
+            PhononError::MeshLoadFailed(entity) => {
+                write!(f, "the mesh of {entity:?} failed to load")
+            }
+            PhononError::MaterialLoadFailed(entity) => {
+                write!(f, "the audio material of {entity:?} failed to load")
+            }
+            #[cfg(feature = "fmod")]
+            PhononError::Spatializer { entity, error } => {
+                write!(f, "spatializer of {entity:?}: {error}")
+            }
+            #[cfg(feature = "fmod")]
+            PhononError::ParameterBinding {
+                entity,
+                parameter,
+                error,
+            } => write!(
+                f,
+                "could not set FMOD parameter {parameter:?} for {entity:?}: {error:?}"
+            ),
+            #[cfg(feature = "fmod")]
+            PhononError::InvalidParameterBand {
+                entity,
+                parameter,
+                output,
+            } => write!(
+                f,
+                "FMOD parameter {parameter:?} of {entity:?} reads {output:?}, bands are 0 to 2"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PhononError {}
+
+impl From<SettingsError> for PhononError {
+    fn from(error: SettingsError) -> Self {
+        PhononError::InvalidSettings(error)
+    }
+}
+
+/// Whether Steam Audio could be started. When it is unavailable none of the systems
+/// of this crate run, so the game can fall back to plain FMOD spatialization.
+#[derive(Resource, Debug)]
+pub enum PhononStatus {
+    Running,
+    Unavailable(PhononError),
+}
+
+impl PhononStatus {
+    pub fn is_running(&self) -> bool {
+        matches!(self, PhononStatus::Running)
+    }
+}
@@ -1,9 +1,43 @@
+pub mod error;
+pub mod phonon_listener;
 pub mod phonon_mesh;
+#[cfg(feature = "fmod")]
+pub mod phonon_parameters;
 pub mod phonon_plugin;
+pub mod phonon_queries;
+pub mod phonon_source;
+pub mod settings;
+mod simulation;
+pub mod simulation_mode;
+mod simulation_thread;
+#[cfg(feature = "fmod")]
+pub mod spatializer;
 
 pub mod prelude {
+    pub use crate::error::{PhononError, PhononStatus};
+    pub use crate::phonon_listener::{PhononActiveListener, PhononListener, PhononListenerOutputs};
     pub use crate::phonon_mesh::material::materials;
-    pub use crate::phonon_mesh::material::PhononMaterial;
-    pub use crate::phonon_mesh::NeedsAudioMesh;
-    pub use crate::phonon_plugin::PhononPlugin;
+    pub use crate::phonon_mesh::material::{AudioMaterial, PhononMaterial};
+    #[cfg(feature = "gltf")]
+    pub use crate::phonon_mesh::material_map::{AutoAudioMesh, PhononMaterialMap};
+    pub use crate::phonon_mesh::mesh::{AudioMeshError, ATTRIBUTE_PHONON_MATERIAL_INDEX};
+    pub use crate::phonon_mesh::{
+        AudioGeometryEnabled, AudioMeshFailed, NeedsAudioMesh, PhononMaterialPalette,
+        PhononMeshMobility,
+    };
+    #[cfg(feature = "fmod")]
+    pub use crate::phonon_parameters::{
+        ParameterCurve, ParameterMapping, ParameterTarget, PhononParameterBinding, SimulationOutput,
+    };
+    pub use crate::phonon_plugin::{PhononPlugin, PhononSet};
+    pub use crate::phonon_queries::{PhononQueries, PhononQueryResult};
+    pub use crate::phonon_source::{
+        OcclusionModel, PhononSourceOutputs, PhononSourcePolicy, PhononSourceSettings,
+    };
+    pub use crate::settings::{
+        PhononBackend, PhononSettings, ReflectionSettings, SimulationExecution,
+    };
+    pub use crate::simulation_mode::{PhononSimulationMode, StageMode};
+    #[cfg(feature = "fmod")]
+    pub use crate::spatializer::params::{ApplyType, HrtfInterpolation, SpatializerParams};
 }
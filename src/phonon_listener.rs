@@ -0,0 +1,186 @@
+use crate::phonon_source::PhononSourceOutputs;
+use crate::simulation::{SimulationInputs, SimulationResults};
+use bevy::prelude::*;
+#[cfg(feature = "fmod")]
+use bevy_fmod::prelude::AudioListener;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Marks the listeners Steam Audio simulates for. When there are several `AudioListener`s,
+/// for example in split-screen, only those with this marker are used.
+/// Every marked listener gets `PhononListenerOutputs`, FMOD plays the sources as heard by
+/// the one in `PhononListener`. Each additional listener costs a simulation of its own.
+/// Without FMOD (`PhononBackend::Headless`) this marker alone makes an entity a listener.
+#[derive(Component, Reflect, Debug, Default, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct PhononActiveListener;
+
+/// The listener Steam Audio is currently simulating for, whose results FMOD plays and
+/// `PhononSourceOutputs` holds. While this is `None` the simulation is paused,
+/// for example during loading screens.
+#[derive(Resource, Reflect, Default, Debug, Deref, Serialize, Deserialize)]
+#[reflect(Resource, Default)]
+pub struct PhononListener(pub Option<Entity>);
+
+/// The outputs of every registered source as heard by this listener, by source entity.
+/// Inserted on the listener in `PhononListener` and on every `PhononActiveListener`.
+/// Not reflected as a component, so scenes leave it out: the source entities would not be
+/// mapped to the loaded ones, and the outputs are simulated again anyway.
+#[derive(Component, Reflect, Debug, Clone, Default, PartialEq)]
+#[reflect(Default)]
+pub struct PhononListenerOutputs(pub HashMap<Entity, PhononSourceOutputs>);
+
+/// Entities that can be the listener.
+#[cfg(feature = "fmod")]
+type ListenerFilter = Or<(With<AudioListener>, With<PhononActiveListener>)>;
+#[cfg(not(feature = "fmod"))]
+type ListenerFilter = With<PhononActiveListener>;
+
+/// Run condition for the simulation.
+pub(crate) fn has_phonon_listener(listener: Res<PhononListener>) -> bool {
+    listener.0.is_some()
+}
+
+pub(crate) fn update_steam_audio_listener(
+    mut inputs: ResMut<SimulationInputs>,
+    mut phonon_listener: ResMut<PhononListener>,
+    listener_query: Query<(Entity, &GlobalTransform, Has<PhononActiveListener>), ListenerFilter>,
+    mut warned: Local<bool>,
+) {
+    let has_active_marker = listener_query.iter().any(|(_, _, active)| active);
+
+    // Prefer marked listeners, the lowest entity keeps the choice stable between frames.
+    let mut candidates = listener_query
+        .iter()
+        .filter(|(_, _, active)| *active || !has_active_marker)
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|(entity, _, _)| *entity);
+
+    if !has_active_marker && candidates.len() > 1 && !*warned {
+        warn!(
+            "Several AudioListeners, Steam Audio only simulates for {:?}. \
+            Add PhononActiveListener to the ones to simulate for.",
+            candidates[0].0
+        );
+        *warned = true;
+    }
+
+    let Some((listener_entity, listener_transform, _)) = candidates.first() else {
+        if phonon_listener.0.is_some() {
+            phonon_listener.0 = None;
+        }
+        return;
+    };
+
+    if phonon_listener.0 != Some(*listener_entity) {
+        phonon_listener.0 = Some(*listener_entity);
+    }
+
+    inputs.listener = Some((*listener_entity, **listener_transform));
+    inputs.extra_listeners = Some(
+        candidates[1..]
+            .iter()
+            .filter(|(_, _, active)| *active)
+            .map(|(entity, transform, _)| (*entity, **transform))
+            .collect(),
+    );
+}
+
+/// Copies the results of the latest finished simulation into `PhononListenerOutputs`.
+pub(crate) fn update_phonon_listener_outputs(
+    mut commands: Commands,
+    results: Res<SimulationResults>,
+    mut listener_query: Query<Option<&mut PhononListenerOutputs>>,
+) {
+    let results = results.read();
+
+    for (listener, listener_results) in &results.listeners {
+        match listener_query.get_mut(*listener) {
+            Ok(Some(mut outputs)) => {
+                if outputs.0 != *listener_results {
+                    outputs.0.clone_from(listener_results);
+                }
+            }
+            Ok(None) => {
+                commands
+                    .entity(*listener)
+                    .insert(PhononListenerOutputs(listener_results.clone()));
+            }
+            // Despawned since the simulation ran.
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phonon_plugin::headless_app;
+    use crate::phonon_source::PhononSourceSettings;
+    use crate::settings::PhononSettings;
+
+    fn spawn_source(app: &mut App) -> Entity {
+        app.world
+            .spawn((
+                PhononSourceSettings::default(),
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, -20.0)),
+            ))
+            .id()
+    }
+
+    fn spawn_listener(app: &mut App, z: f32) -> Entity {
+        app.world
+            .spawn((
+                PhononActiveListener,
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, z)),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn simulation_waits_for_a_listener() {
+        let mut app = headless_app(PhononSettings::default());
+        let source = spawn_source(&mut app);
+
+        for _ in 0..3 {
+            app.update();
+        }
+        assert_eq!(app.world.resource::<PhononListener>().0, None);
+        assert_eq!(
+            app.world.get::<PhononSourceOutputs>(source),
+            Some(&PhononSourceOutputs::default())
+        );
+
+        let listener = spawn_listener(&mut app, 0.0);
+        app.update();
+        app.update();
+
+        assert_eq!(app.world.resource::<PhononListener>().0, Some(listener));
+        let outputs = app.world.get::<PhononSourceOutputs>(source).unwrap();
+        assert!(outputs.distance_attenuation < 1.0);
+        let listener_outputs = app.world.get::<PhononListenerOutputs>(listener).unwrap();
+        assert_eq!(listener_outputs.0.get(&source), Some(outputs));
+    }
+
+    #[test]
+    fn every_active_listener_gets_outputs() {
+        let mut app = headless_app(PhononSettings::default());
+        let source = spawn_source(&mut app);
+        let near = spawn_listener(&mut app, -18.0);
+        let far = spawn_listener(&mut app, 0.0);
+        app.update();
+        app.update();
+
+        let attenuation = |app: &App, listener: Entity| {
+            app.world.get::<PhononListenerOutputs>(listener).unwrap().0[&source]
+                .distance_attenuation
+        };
+        assert!(attenuation(&app, near) > attenuation(&app, far));
+
+        app.world.despawn(near);
+        app.update();
+        app.update();
+        assert_eq!(app.world.resource::<PhononListener>().0, Some(far));
+        assert!(attenuation(&app, far) < 1.0);
+    }
+}
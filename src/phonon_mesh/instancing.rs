@@ -1,15 +1,38 @@
+use crate::error::PhononError;
 use crate::phonon_mesh::material::PhononMaterial;
 use crate::phonon_mesh::mesh;
 use crate::phonon_mesh::mesh::AudioMesh;
 use crate::phonon_plugin::SteamSimulation;
 use bevy::asset::{Assets, Handle};
 use bevy::ecs::system::SystemParam;
-use bevy::prelude::{Deref, DerefMut, Mesh, ResMut, Resource, Transform};
+use bevy::prelude::{Deref, DerefMut, Entity, GlobalTransform, Mesh, ResMut, Resource, Transform};
 use std::collections::HashMap;
-use steamaudio::scene::InstancedMesh;
+use steamaudio::scene::{InstancedMesh, StaticMesh};
 
+pub(crate) type StaticMeshKey = (Handle<Mesh>, Vec<PhononMaterial>);
+
+/// Converted Bevy meshes, each in their own sub scene so they can be instanced.
 #[derive(Resource, Default, Deref, DerefMut)]
-pub(crate) struct StaticMeshes(HashMap<(Handle<Mesh>, PhononMaterial), steamaudio::scene::Scene>);
+pub(crate) struct StaticMeshes(HashMap<StaticMeshKey, CachedSubScene>);
+
+pub(crate) struct CachedSubScene {
+    pub(crate) scene: steamaudio::scene::Scene,
+    /// Number of instanced meshes using this sub scene.
+    pub(crate) users: usize,
+}
+
+impl StaticMeshes {
+    /// Drops the sub scene once the last instanced mesh using it is gone.
+    pub(crate) fn release(&mut self, key: &StaticMeshKey) {
+        if let Some(cached) = self.get_mut(key) {
+            cached.users = cached.users.saturating_sub(1);
+
+            if cached.users == 0 {
+                self.remove(key);
+            }
+        }
+    }
+}
 
 /// Some information necessary to convert Bevy meshes to Steam Audio meshes
 #[derive(SystemParam)]
@@ -24,40 +47,82 @@ impl<'w> MeshParam<'w> {
     /// If the Bevy mesh has been converted before it will re-use the Steam Audio mesh.
     pub(crate) fn create_instanced_mesh(
         &mut self,
+        entity: Entity,
         mesh_handle: &Handle<Mesh>,
-        material: &PhononMaterial,
-    ) -> Option<InstancedMesh> {
-        create_instanced_mesh_internal(self, mesh_handle, material)
+        materials: &[PhononMaterial],
+    ) -> Result<InstancedMesh, PhononError> {
+        create_instanced_mesh_internal(self, entity, mesh_handle, materials)
+    }
+
+    /// Adds a Bevy Mesh to the root scene as a Steam Audio Static Mesh.
+    /// The vertices are transformed to world space once, so the mesh can not be moved afterwards.
+    pub(crate) fn create_static_mesh(
+        &mut self,
+        entity: Entity,
+        mesh_handle: &Handle<Mesh>,
+        materials: &[PhononMaterial],
+        transform: &GlobalTransform,
+    ) -> Result<StaticMesh, PhononError> {
+        let Some(mesh) = self.bevy_meshes.get(mesh_handle) else {
+            return Err(PhononError::MeshAssetNotFound(entity));
+        };
+
+        let mut audio_mesh: AudioMesh = mesh::try_from(mesh, materials)
+            .map_err(|error| PhononError::AudioMesh { entity, error })?;
+        audio_mesh.transform(transform.compute_matrix());
+
+        self.simulator
+            .scene
+            .create_static_mesh(
+                audio_mesh.triangles.as_slice(),
+                audio_mesh.vertices.as_slice(),
+                audio_mesh.material_indices.as_slice(),
+                audio_mesh.materials.as_slice(),
+            )
+            .map_err(|error| PhononError::MeshCreation {
+                entity,
+                error: format!("{error:?}"),
+            })
     }
 }
 
 fn create_instanced_mesh_internal(
     mesh_param: &mut MeshParam,
+    entity: Entity,
     mesh_handle: &Handle<Mesh>,
-    material: &PhononMaterial,
-) -> Option<InstancedMesh> {
+    materials: &[PhononMaterial],
+) -> Result<InstancedMesh, PhononError> {
+    let mesh_creation_error = |error| PhononError::MeshCreation {
+        entity,
+        error: format!("{error:?}"),
+    };
+
     let static_meshes = &mut mesh_param.static_meshes;
     let meshes = &mesh_param.bevy_meshes;
     let simulator = &mesh_param.simulator;
     let scene_root = &simulator.scene;
 
-    if let Some(static_mesh_scene) = static_meshes.get(&(mesh_handle.clone(), material.clone())) {
-        // Turn that mesh into an instanced one, so it can be moved around.
-        // todo: Differentiate between set-and-forget and movable audio meshes.
-        // Currently compute_matrix will be called every frame for every mesh.
+    let key = (mesh_handle.clone(), materials.to_vec());
 
+    if let Some(cached) = static_meshes.get_mut(&key) {
+        // Turn that mesh into an instanced one, so it can be moved around.
         let instanced_mesh = scene_root
-            .create_instanced_mesh(static_mesh_scene, Transform::default().compute_matrix())
-            .unwrap();
+            .create_instanced_mesh(&cached.scene, Transform::default().compute_matrix())
+            .map_err(mesh_creation_error)?;
+        cached.users += 1;
 
-        Some(instanced_mesh)
+        Ok(instanced_mesh)
     } else {
         // Create audio geometry
         if let Some(mesh) = meshes.get(&*mesh_handle) {
-            let audio_mesh: AudioMesh = mesh::try_from(mesh, material).unwrap();
+            let audio_mesh: AudioMesh = mesh::try_from(mesh, materials)
+                .map_err(|error| PhononError::AudioMesh { entity, error })?;
 
             // Create sub scene with static mesh, this will later be used to create the instanced mesh
-            let sub_scene = simulator.context.create_scene().unwrap();
+            let sub_scene = simulator
+                .context
+                .create_scene()
+                .map_err(mesh_creation_error)?;
 
             // Add mesh
             let mut static_mesh = sub_scene
@@ -67,22 +132,27 @@ fn create_instanced_mesh_internal(
                     audio_mesh.material_indices.as_slice(),
                     audio_mesh.materials.as_slice(),
                 )
-                .unwrap();
+                .map_err(mesh_creation_error)?;
             static_mesh.set_visible(true);
             sub_scene.commit();
 
-            static_meshes.insert((mesh_handle.clone(), material.clone()), sub_scene.clone());
-
             // Turn that mesh into an instanced one, so it can be moved around.
-            // todo: Differentiate between set-and-forget and movable audio meshes.
-            // Currently compute_matrix will be called every frame for every mesh.
             let instanced_mesh = scene_root
                 .create_instanced_mesh(&sub_scene, Transform::default().compute_matrix())
-                .unwrap();
+                .map_err(mesh_creation_error)?;
+
+            static_meshes.insert(
+                key,
+                CachedSubScene {
+                    scene: sub_scene,
+                    users: 1,
+                },
+            );
 
-            Some(instanced_mesh)
+            Ok(instanced_mesh)
         } else {
-            None // todo: Improve this mess. There is also a bit of duplicated code above
+            // todo: Improve this mess. There is also a bit of duplicated code above
+            Err(PhononError::MeshAssetNotFound(entity))
         }
     }
 }
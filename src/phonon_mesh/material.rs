@@ -1,9 +1,24 @@
 // Mostly copied from https://github.com/Aceeri/steam-audio-rs/blob/master/steam-audio/src/simulation/material.rs
 
+#[cfg(feature = "material_files")]
+use bevy::asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext};
+use bevy::asset::{Asset, Handle};
+use bevy::reflect::{std_traits::ReflectDefault, Reflect};
+#[cfg(feature = "material_files")]
+use bevy::utils::BoxedFuture;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "material_files")]
+use std::fmt;
 use std::hash::{DefaultHasher, Hash, Hasher};
+#[cfg(feature = "material_files")]
+use std::path::Path;
 
 /// Acoustic properties of a surface.
-#[derive(Debug, Clone, PartialEq)]
+/// With the `material_files` feature it can also be loaded from `.phonon_material.ron`
+/// and `.phonon_material.json` files:
+/// `(absorption: (0.1, 0.2, 0.3), scattering: 0.05, transmission: (0.1, 0.05, 0.03))`
+#[derive(Asset, Reflect, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[reflect(Default)]
 pub struct PhononMaterial {
     /// Specified in 3 frequency bands of 400 Hz, 2.5KHz, and 15 KHz.
     pub absorption: [f32; 3],
@@ -56,6 +71,109 @@ impl Default for PhononMaterial {
     }
 }
 
+/// The material of audio geometry, either given directly or loaded from a file.
+/// Geometry made from an asset is rebuilt when the file changes.
+/// Only `Reflect`, not serde: a `Handle` can not be deserialized without the `AssetServer`.
+/// Scenes can store `Inline` materials, for assets use an entity that sets the handle
+/// after loading.
+#[derive(Reflect, Debug, Clone, PartialEq)]
+#[reflect(Default)]
+pub enum AudioMaterial {
+    Inline(PhononMaterial),
+    Asset(Handle<PhononMaterial>),
+}
+
+impl Default for AudioMaterial {
+    fn default() -> Self {
+        AudioMaterial::Inline(PhononMaterial::default())
+    }
+}
+
+impl From<PhononMaterial> for AudioMaterial {
+    fn from(material: PhononMaterial) -> Self {
+        AudioMaterial::Inline(material)
+    }
+}
+
+impl From<Handle<PhononMaterial>> for AudioMaterial {
+    fn from(handle: Handle<PhononMaterial>) -> Self {
+        AudioMaterial::Asset(handle)
+    }
+}
+
+#[cfg(feature = "material_files")]
+#[derive(Default)]
+pub struct PhononMaterialLoader;
+
+#[cfg(feature = "material_files")]
+#[derive(Debug)]
+pub enum PhononMaterialLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    Json(serde_json::Error),
+}
+
+#[cfg(feature = "material_files")]
+impl fmt::Display for PhononMaterialLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PhononMaterialLoaderError::Io(error) => write!(f, "could not read the file: {error}"),
+            PhononMaterialLoaderError::Ron(error) => write!(f, "invalid RON: {error}"),
+            PhononMaterialLoaderError::Json(error) => write!(f, "invalid JSON: {error}"),
+        }
+    }
+}
+
+#[cfg(feature = "material_files")]
+impl std::error::Error for PhononMaterialLoaderError {}
+
+#[cfg(feature = "material_files")]
+impl From<std::io::Error> for PhononMaterialLoaderError {
+    fn from(error: std::io::Error) -> Self {
+        PhononMaterialLoaderError::Io(error)
+    }
+}
+
+#[cfg(feature = "material_files")]
+impl PhononMaterialLoader {
+    /// Files ending in `.json` are read as JSON, all others as RON.
+    fn parse(path: &Path, bytes: &[u8]) -> Result<PhononMaterial, PhononMaterialLoaderError> {
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension == "json");
+
+        if is_json {
+            serde_json::from_slice(bytes).map_err(PhononMaterialLoaderError::Json)
+        } else {
+            ron::de::from_bytes(bytes).map_err(PhononMaterialLoaderError::Ron)
+        }
+    }
+}
+
+#[cfg(feature = "material_files")]
+impl AssetLoader for PhononMaterialLoader {
+    type Asset = PhononMaterial;
+    type Settings = ();
+    type Error = PhononMaterialLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Self::parse(load_context.path(), &bytes)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["phonon_material.ron", "phonon_material.json"]
+    }
+}
+
 #[allow(unused)]
 pub mod materials {
     use super::PhononMaterial;
@@ -116,3 +234,55 @@ pub mod materials {
         transmission: [0.015, 0.002, 0.001],
     };
 }
+
+#[cfg(all(test, feature = "material_files"))]
+mod tests {
+    use super::*;
+
+    /// The example of the `PhononMaterial` documentation.
+    const EXAMPLE_RON: &str =
+        "(absorption: (0.1, 0.2, 0.3), scattering: 0.05, transmission: (0.1, 0.05, 0.03))";
+    const EXAMPLE_JSON: &str =
+        r#"{"absorption": [0.1, 0.2, 0.3], "scattering": 0.05, "transmission": [0.1, 0.05, 0.03]}"#;
+
+    #[test]
+    fn documented_example_is_valid() {
+        let material: PhononMaterial = ron::de::from_str(EXAMPLE_RON).unwrap();
+        assert_eq!(material, materials::GENERIC);
+
+        let material: PhononMaterial = serde_json::from_str(EXAMPLE_JSON).unwrap();
+        assert_eq!(material, materials::GENERIC);
+    }
+
+    #[test]
+    fn extension_picks_the_format() {
+        let ron_path = Path::new("materials/generic.phonon_material.ron");
+        let json_path = Path::new("materials/generic.phonon_material.json");
+
+        let material = PhononMaterialLoader::parse(ron_path, EXAMPLE_RON.as_bytes()).unwrap();
+        assert_eq!(material, materials::GENERIC);
+        let material = PhononMaterialLoader::parse(json_path, EXAMPLE_JSON.as_bytes()).unwrap();
+        assert_eq!(material, materials::GENERIC);
+
+        assert!(matches!(
+            PhononMaterialLoader::parse(json_path, EXAMPLE_RON.as_bytes()),
+            Err(PhononMaterialLoaderError::Json(_))
+        ));
+        assert!(matches!(
+            PhononMaterialLoader::parse(ron_path, EXAMPLE_JSON.as_bytes()),
+            Err(PhononMaterialLoaderError::Ron(_))
+        ));
+    }
+
+    #[test]
+    fn missing_fields_are_rejected() {
+        let incomplete = "(absorption: (0.1, 0.2, 0.3), scattering: 0.05)";
+        assert!(matches!(
+            PhononMaterialLoader::parse(
+                Path::new("incomplete.phonon_material.ron"),
+                incomplete.as_bytes()
+            ),
+            Err(PhononMaterialLoaderError::Ron(_))
+        ));
+    }
+}
@@ -0,0 +1,450 @@
+use crate::phonon_mesh::material::PhononMaterial;
+use crate::phonon_mesh::{NeedsAudioMesh, PhononMesh, PhononMeshMobility};
+use bevy::ecs::system::SystemParam;
+use bevy::gltf::{Gltf, GltfExtras};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Rules to pick a `PhononMaterial` for meshes that did not get one by hand.
+/// Used for every mesh below an entity with `AutoAudioMesh`, for example a glTF scene.
+///
+/// The first rule that matches wins, in this order:
+/// 1. The `Handle<StandardMaterial>` of the mesh, see `insert_handle`.
+/// 2. The `extras_key` field in the glTF extras of the mesh or its parents,
+///    its string value is matched against the name rules.
+/// 3. The glTF material name, then the `Name` of the mesh and its parents,
+///    matched against the name rules.
+/// 4. `fallback`. Without one the mesh gets no audio geometry.
+///
+/// Only `Reflect`, the handle rules refer to assets of the running app and can not be
+/// serialized.
+#[derive(Resource, Reflect, Debug, Clone)]
+#[reflect(Resource, Default)]
+pub struct PhononMaterialMap {
+    handles: HashMap<AssetId<StandardMaterial>, PhononMaterial>,
+    names: Vec<(String, PhononMaterial)>,
+    /// The glTF extras field holding the material name, e.g. `{"phonon_material": "glass"}`.
+    pub extras_key: String,
+    pub fallback: Option<PhononMaterial>,
+}
+
+impl Default for PhononMaterialMap {
+    fn default() -> Self {
+        Self {
+            handles: HashMap::new(),
+            names: Vec::new(),
+            extras_key: "phonon_material".to_owned(),
+            fallback: None,
+        }
+    }
+}
+
+impl PhononMaterialMap {
+    pub fn insert_handle(
+        &mut self,
+        handle: impl Into<AssetId<StandardMaterial>>,
+        material: PhononMaterial,
+    ) -> &mut Self {
+        self.handles.insert(handle.into(), material);
+        self
+    }
+
+    /// Adds a name rule. `*` matches any number of characters and matching ignores
+    /// ASCII case, so `"*_glass"` matches `"Window_Glass"`. Rules are tried in the order
+    /// they were added.
+    pub fn insert_name(
+        &mut self,
+        pattern: impl Into<String>,
+        material: PhononMaterial,
+    ) -> &mut Self {
+        self.names.push((pattern.into(), material));
+        self
+    }
+
+    pub fn with_handle(
+        mut self,
+        handle: impl Into<AssetId<StandardMaterial>>,
+        material: PhononMaterial,
+    ) -> Self {
+        self.insert_handle(handle, material);
+        self
+    }
+
+    pub fn with_name(mut self, pattern: impl Into<String>, material: PhononMaterial) -> Self {
+        self.insert_name(pattern, material);
+        self
+    }
+
+    pub fn with_fallback(mut self, material: PhononMaterial) -> Self {
+        self.fallback = Some(material);
+        self
+    }
+
+    pub fn by_handle(&self, id: AssetId<StandardMaterial>) -> Option<&PhononMaterial> {
+        self.handles.get(&id)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&PhononMaterial> {
+        self.names
+            .iter()
+            .find(|(pattern, _)| matches_pattern(pattern, name))
+            .map(|(_, material)| material)
+    }
+
+    /// The material name stored under `extras_key` in a glTF extras JSON object.
+    fn extras_name(&self, extras: &GltfExtras) -> Option<String> {
+        let value: serde_json::Value = serde_json::from_str(&extras.value).ok()?;
+        value.get(&self.extras_key)?.as_str().map(str::to_owned)
+    }
+}
+
+/// Glob style matching with `*` wildcards, ignoring ASCII case.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let name = name.to_ascii_lowercase();
+
+    let mut parts = pattern.split('*');
+    // `split` always yields at least one part.
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard, the whole name has to match.
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+/// Gives every mesh below this entity audio geometry, with the material chosen by
+/// `PhononMaterialMap`. Add it next to a `SceneBundle` of a glTF file.
+/// Meshes that already have `NeedsAudioMesh` keep their material.
+/// A `PhononMeshMobility` on this entity is copied to the meshes.
+#[derive(Component, Reflect, Debug, Default, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct AutoAudioMesh;
+
+/// The names glTF files gave to their `StandardMaterial`s, only glTF files know them.
+/// Rebuilt when a glTF asset is added, modified or removed. Stays empty without `GltfPlugin`.
+#[derive(Resource, Default)]
+pub(crate) struct GltfMaterialNames(HashMap<AssetId<StandardMaterial>, String>);
+
+impl GltfMaterialNames {
+    fn rebuild(&mut self, gltfs: &Assets<Gltf>) {
+        self.0 = gltfs
+            .iter()
+            .flat_map(|(_, gltf)| gltf.named_materials.iter())
+            .map(|(name, handle)| (handle.id(), name.clone()))
+            .collect();
+    }
+
+    fn get(&self, id: AssetId<StandardMaterial>) -> Option<&str> {
+        self.0.get(&id).map(String::as_str)
+    }
+}
+
+/// Where to look for hints about the material of a mesh.
+#[derive(SystemParam)]
+pub(crate) struct MaterialHints<'w, 's> {
+    material_names: Res<'w, GltfMaterialNames>,
+    parents: Query<'w, 's, &'static Parent>,
+    children: Query<'w, 's, &'static Children>,
+    extras: Query<'w, 's, &'static GltfExtras>,
+    names: Query<'w, 's, &'static Name>,
+}
+
+/// Only runs when `Assets<Gltf>` exists, i.e. with `GltfPlugin`.
+pub(crate) fn update_gltf_material_names(
+    gltfs: Res<Assets<Gltf>>,
+    mut gltf_events: EventReader<AssetEvent<Gltf>>,
+    mut material_names: ResMut<GltfMaterialNames>,
+) {
+    if !gltf_events.is_empty() {
+        gltf_events.clear();
+        material_names.rebuild(&gltfs);
+    }
+}
+
+/// Adds `NeedsAudioMesh` to meshes spawned below an `AutoAudioMesh`, and to the meshes
+/// below an entity that just got `AutoAudioMesh`.
+pub(crate) fn attach_auto_audio_meshes(
+    mut commands: Commands,
+    material_map: Res<PhononMaterialMap>,
+    added_meshes: Query<Entity, Added<Handle<Mesh>>>,
+    added_roots: Query<Entity, Added<AutoAudioMesh>>,
+    roots: Query<Option<&PhononMeshMobility>, With<AutoAudioMesh>>,
+    mesh_query: Query<
+        Option<&Handle<StandardMaterial>>,
+        (
+            With<Handle<Mesh>>,
+            Without<NeedsAudioMesh>,
+            Without<PhononMesh>,
+        ),
+    >,
+    hints: MaterialHints,
+) {
+    let candidates =
+        added_meshes
+            .iter()
+            .chain(added_roots.iter().flat_map(|root| {
+                std::iter::once(root).chain(hints.children.iter_descendants(root))
+            }))
+            .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        return;
+    }
+
+    for ent in candidates {
+        let Ok(standard_material) = mesh_query.get(ent) else {
+            continue;
+        };
+
+        // The mesh itself followed by its parents, up to the closest `AutoAudioMesh`.
+        let mut lineage = Vec::new();
+        let mut root_mobility = None;
+        for ancestor in std::iter::once(ent).chain(hints.parents.iter_ancestors(ent)) {
+            lineage.push(ancestor);
+            if let Ok(mobility) = roots.get(ancestor) {
+                root_mobility = Some(mobility);
+                break;
+            }
+        }
+        let Some(mobility) = root_mobility else {
+            continue;
+        };
+
+        let material = standard_material
+            .and_then(|handle| material_map.by_handle(handle.id()))
+            .or_else(|| {
+                lineage.iter().find_map(|ent| {
+                    let extras = hints.extras.get(*ent).ok()?;
+                    material_map.by_name(&material_map.extras_name(extras)?)
+                })
+            })
+            .or_else(|| {
+                let name = hints.material_names.get(standard_material?.id())?;
+                material_map.by_name(name)
+            })
+            .or_else(|| {
+                lineage
+                    .iter()
+                    .find_map(|ent| material_map.by_name(hints.names.get(*ent).ok()?.as_str()))
+            })
+            .or(material_map.fallback.as_ref());
+
+        let Some(material) = material else {
+            continue;
+        };
+
+        let mut entity_commands = commands.entity(ent);
+        entity_commands.insert(NeedsAudioMesh::new(material.clone()));
+        if let Some(mobility) = mobility {
+            entity_commands.insert(*mobility);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phonon_mesh::material::{materials, AudioMaterial};
+    use crate::phonon_plugin::headless_app;
+    use crate::settings::PhononSettings;
+
+    fn gltf_app(material_map: PhononMaterialMap) -> App {
+        let mut app = headless_app(PhononSettings::default());
+        app.init_asset::<Gltf>()
+            .init_asset::<StandardMaterial>()
+            .insert_resource(material_map);
+        app
+    }
+
+    /// A glTF file that only names its materials.
+    fn named_gltf(named_materials: &[(&str, &Handle<StandardMaterial>)]) -> Gltf {
+        Gltf {
+            scenes: default(),
+            named_scenes: default(),
+            meshes: default(),
+            named_meshes: default(),
+            materials: named_materials
+                .iter()
+                .map(|(_, handle)| (*handle).clone())
+                .collect(),
+            named_materials: named_materials
+                .iter()
+                .map(|(name, handle)| (name.to_string(), (*handle).clone()))
+                .collect(),
+            nodes: default(),
+            named_nodes: default(),
+            default_scene: None,
+            animations: default(),
+            named_animations: default(),
+            source: None,
+        }
+    }
+
+    /// Spawns a mesh with the given material below a new `AutoAudioMesh`.
+    fn spawn_scene(app: &mut App, standard_material: Handle<StandardMaterial>) -> Entity {
+        let mesh = app
+            .world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Cuboid::default());
+        let mut child = None;
+        app.world
+            .spawn((
+                AutoAudioMesh,
+                PhononMeshMobility::Static,
+                SpatialBundle::default(),
+            ))
+            .with_children(|parent| {
+                child = Some(
+                    parent
+                        .spawn((mesh, standard_material, SpatialBundle::default()))
+                        .id(),
+                );
+            });
+        child.unwrap()
+    }
+
+    fn material_of(app: &App, ent: Entity) -> Option<PhononMaterial> {
+        match &app.world.get::<NeedsAudioMesh>(ent)?.0 {
+            AudioMaterial::Inline(material) => Some(material.clone()),
+            AudioMaterial::Asset(_) => None,
+        }
+    }
+
+    #[test]
+    fn meshes_get_the_material_of_their_gltf_name() {
+        let mut app = gltf_app(
+            PhononMaterialMap::default()
+                .with_name("*_glass", materials::GLASS)
+                .with_name("concrete*", materials::CONCRETE),
+        );
+        let window = app
+            .world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(StandardMaterial::default());
+        let gltf = app
+            .world
+            .resource_mut::<Assets<Gltf>>()
+            .add(named_gltf(&[("Window_Glass", &window)]));
+        app.update();
+
+        let mesh = spawn_scene(&mut app, window.clone());
+        app.update();
+        assert_eq!(material_of(&app, mesh), Some(materials::GLASS));
+        assert_eq!(
+            app.world.get::<PhononMeshMobility>(mesh),
+            Some(&PhononMeshMobility::Static)
+        );
+
+        // A reloaded file with other names.
+        let wall = app
+            .world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(StandardMaterial::default());
+        app.world
+            .resource_mut::<Assets<Gltf>>()
+            .insert(gltf.id(), named_gltf(&[("Concrete_Wall", &wall)]));
+        app.update();
+
+        let mesh = spawn_scene(&mut app, wall);
+        app.update();
+        assert_eq!(material_of(&app, mesh), Some(materials::CONCRETE));
+
+        // The name of the old file is gone.
+        let mesh = spawn_scene(&mut app, window);
+        app.update();
+        assert_eq!(material_of(&app, mesh), None);
+    }
+
+    #[test]
+    fn handles_win_over_names_and_the_fallback() {
+        let mut app = headless_app(PhononSettings::default());
+        app.init_asset::<Gltf>().init_asset::<StandardMaterial>();
+        let standard_material = app
+            .world
+            .resource_mut::<Assets<StandardMaterial>>()
+            .add(StandardMaterial::default());
+        app.insert_resource(
+            PhononMaterialMap::default()
+                .with_handle(&standard_material, materials::METAL)
+                .with_name("*", materials::WOOD)
+                .with_fallback(materials::CARPET),
+        );
+
+        let by_handle = spawn_scene(&mut app, standard_material);
+        let by_name = spawn_scene(&mut app, Handle::default());
+        app.world.entity_mut(by_name).insert(Name::new("Table"));
+        let by_hand = spawn_scene(&mut app, Handle::default());
+        app.world
+            .entity_mut(by_hand)
+            .insert(NeedsAudioMesh::new(materials::ROCK));
+        let fallback = spawn_scene(&mut app, Handle::default());
+        app.update();
+
+        assert_eq!(material_of(&app, by_handle), Some(materials::METAL));
+        assert_eq!(material_of(&app, by_name), Some(materials::WOOD));
+        assert_eq!(material_of(&app, by_hand), Some(materials::ROCK));
+        assert_eq!(material_of(&app, fallback), Some(materials::CARPET));
+    }
+
+    #[test]
+    fn names_work_without_the_gltf_plugin() {
+        let mut app = headless_app(PhononSettings::default());
+        app.insert_resource(
+            PhononMaterialMap::default()
+                .with_name("Table", materials::WOOD)
+                .with_fallback(materials::CARPET),
+        );
+
+        let by_name = spawn_scene(&mut app, Handle::default());
+        app.world.entity_mut(by_name).insert(Name::new("Table"));
+        let fallback = spawn_scene(&mut app, Handle::default());
+        app.update();
+
+        assert_eq!(material_of(&app, by_name), Some(materials::WOOD));
+        assert_eq!(material_of(&app, fallback), Some(materials::CARPET));
+    }
+
+    #[test]
+    fn wildcards_match_any_characters() {
+        assert!(matches_pattern("*_glass", "window_glass"));
+        assert!(matches_pattern("window_*", "window_glass"));
+        assert!(matches_pattern("window*glass", "window_stained_glass"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("a*b*c", "abc"));
+    }
+
+    #[test]
+    fn without_wildcard_the_whole_name_matches() {
+        assert!(matches_pattern("glass", "glass"));
+        assert!(!matches_pattern("glass", "glass_window"));
+        assert!(!matches_pattern("glass", "stained_glass"));
+    }
+
+    #[test]
+    fn case_is_ignored() {
+        assert!(matches_pattern("*_glass", "Window_Glass"));
+        assert!(matches_pattern("CONCRETE", "concrete"));
+    }
+
+    #[test]
+    fn parts_do_not_overlap() {
+        assert!(!matches_pattern("ab*b", "ab"));
+        assert!(!matches_pattern("a*a", "a"));
+        assert!(!matches_pattern("*_glass", "glass"));
+    }
+}
@@ -1,6 +1,13 @@
 use crate::prelude::PhononMaterial;
-use bevy::prelude::Mesh;
-use bevy::render::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
+use bevy::prelude::{Mat4, Mesh, Vec3};
+use bevy::render::mesh::{Indices, MeshVertexAttribute, PrimitiveTopology, VertexAttributeValues};
+use bevy::render::render_resource::VertexFormat;
+
+/// Per vertex index into the `PhononMaterialPalette` of the entity.
+/// A triangle gets the material of its first vertex. Without this attribute
+/// the whole mesh uses the first material.
+pub const ATTRIBUTE_PHONON_MATERIAL_INDEX: MeshVertexAttribute =
+    MeshVertexAttribute::new("Phonon_Material_Index", 2_206_774_392, VertexFormat::Uint32);
 
 pub struct AudioMesh {
     pub vertices: Vec<[f32; 3]>,
@@ -9,59 +16,134 @@ pub struct AudioMesh {
     pub material_indices: Vec<u32>,
 }
 
+impl AudioMesh {
+    /// Moves the vertices to where `matrix` puts them, for geometry that is added in world space.
+    pub fn transform(&mut self, matrix: Mat4) {
+        for vertex in &mut self.vertices {
+            *vertex = matrix.transform_point3(Vec3::from(*vertex)).into();
+        }
+
+        // A mirroring transform turns the triangles inside out, flip them back.
+        if matrix.determinant() < 0.0 {
+            for triangle in &mut self.triangles {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+}
+
+/// Triangles with less area than this fraction of the squared diagonal of the mesh bounds
+/// are dropped. Relative, so small or scaled up meshes lose the same triangles.
+const MIN_RELATIVE_TRIANGLE_AREA: f32 = 1e-10;
+
 #[derive(Debug, Clone)]
 pub enum AudioMeshError {
+    /// The mesh has no `Mesh::ATTRIBUTE_POSITION`.
     NoVertices,
+    /// Only `Float32x3` and `Float32x4` positions can be converted.
+    UnsupportedPositionFormat(VertexFormat),
     NonTrianglePrimitiveTopology(PrimitiveTopology),
+    /// An index refers to a vertex that does not exist.
+    IndexOutOfRange {
+        index: u32,
+        num_vertices: usize,
+    },
+    /// Every triangle is degenerate, or there are fewer than 3 vertices.
+    NoTriangles,
+    /// The `PhononMaterialPalette` is empty.
+    NoMaterials,
+    /// `ATTRIBUTE_PHONON_MATERIAL_INDEX` has to be `Uint32`.
+    UnsupportedMaterialIndexFormat(VertexFormat),
+    /// `ATTRIBUTE_PHONON_MATERIAL_INDEX` points past the end of the palette.
+    MaterialIndexOutOfRange {
+        index: u32,
+        num_materials: usize,
+    },
+    /// `ATTRIBUTE_PHONON_MATERIAL_INDEX` has fewer values than the mesh has vertices.
+    MissingMaterialIndex {
+        vertex: u32,
+        num_material_indices: usize,
+    },
 }
 
 // Original code from https://github.com/Aceeri/bevy-steam-audio/blob/main/src/source.rs
-pub fn try_from(mesh: &Mesh, material: &PhononMaterial) -> Result<AudioMesh, AudioMeshError> {
-    let triangles = match mesh.indices() {
-        Some(indices) => {
-            let indices: Vec<_> = match indices {
-                Indices::U16(indices) => indices.iter().map(|indices| *indices as u32).collect(),
-                Indices::U32(indices) => indices.iter().map(|indices| *indices).collect(),
-            };
-
-            match mesh.primitive_topology() {
-                PrimitiveTopology::TriangleList => indices
-                    .chunks_exact(3)
-                    .map(|chunk| [chunk[0], chunk[1], chunk[2]])
-                    .collect(),
-                PrimitiveTopology::TriangleStrip => {
-                    let mut indices: Vec<_> = indices
-                        .windows(3)
-                        .map(|indices| [indices[0], indices[1], indices[2]])
-                        .collect();
-
-                    for (index, indices) in indices.iter_mut().enumerate() {
-                        if (index + 1) % 2 == 0 {
-                            *indices = [indices[1], indices[0], indices[2]];
-                        }
-                    }
+pub fn try_from(mesh: &Mesh, materials: &[PhononMaterial]) -> Result<AudioMesh, AudioMeshError> {
+    if materials.is_empty() {
+        return Err(AudioMeshError::NoMaterials);
+    }
 
-                    indices
-                }
-                topology => return Err(AudioMeshError::NonTrianglePrimitiveTopology(topology)),
-            }
+    let vertices: Vec<[f32; 3]> = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float32x3(vertices)) => vertices.clone(),
+        Some(VertexAttributeValues::Float32x4(vertices)) => {
+            vertices.iter().map(|[x, y, z, _]| [*x, *y, *z]).collect()
         }
-        None => Vec::new(),
+        Some(positions) => return Err(AudioMeshError::UnsupportedPositionFormat(positions.into())),
+        None => return Err(AudioMeshError::NoVertices),
     };
 
-    let vertices = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
-        Some(positions) => match positions {
-            VertexAttributeValues::Float32x3(vertices) => {
-                vertices.iter().map(|a| (*a).into()).collect()
-            }
-            _ => return Err(AudioMeshError::NoVertices),
-        },
-        _ => return Err(AudioMeshError::NoVertices),
+    // Meshes without indices use every vertex once, in order.
+    let (indices, restart_index): (Vec<u32>, u32) = match mesh.indices() {
+        Some(Indices::U16(indices)) => (
+            indices.iter().map(|index| *index as u32).collect(),
+            u16::MAX as u32,
+        ),
+        Some(Indices::U32(indices)) => (indices.clone(), u32::MAX),
+        None => ((0..vertices.len() as u32).collect(), u32::MAX),
+    };
+
+    let triangles = match mesh.primitive_topology() {
+        PrimitiveTopology::TriangleList => indices
+            .chunks_exact(3)
+            .map(|chunk| [chunk[0], chunk[1], chunk[2]])
+            .collect(),
+        // The maximum value of the index format restarts the strip.
+        // Lists have no restarts, there it is an ordinary index.
+        PrimitiveTopology::TriangleStrip => indices
+            .split(|index| *index == restart_index)
+            .flat_map(|strip| {
+                strip.windows(3).enumerate().map(|(position, window)| {
+                    // Every other triangle of a strip is wound the other way around.
+                    if position % 2 == 1 {
+                        [window[1], window[0], window[2]]
+                    } else {
+                        [window[0], window[1], window[2]]
+                    }
+                })
+            })
+            .collect(),
+        topology => return Err(AudioMeshError::NonTrianglePrimitiveTopology(topology)),
     };
 
-    let material: steamaudio::scene::Material = material.into();
-    let materials = vec![material];
-    let material_indices = triangles.iter().map(|_| 0 /* GENERIC index */).collect();
+    let triangles = remove_degenerate_triangles(triangles, &vertices)?;
+
+    let material_indices = match mesh.attribute(ATTRIBUTE_PHONON_MATERIAL_INDEX) {
+        Some(VertexAttributeValues::Uint32(vertex_materials)) => triangles
+            .iter()
+            .map(|triangle: &[u32; 3]| {
+                let index = *vertex_materials.get(triangle[0] as usize).ok_or(
+                    AudioMeshError::MissingMaterialIndex {
+                        vertex: triangle[0],
+                        num_material_indices: vertex_materials.len(),
+                    },
+                )?;
+                if index as usize >= materials.len() {
+                    return Err(AudioMeshError::MaterialIndexOutOfRange {
+                        index,
+                        num_materials: materials.len(),
+                    });
+                }
+                Ok(index)
+            })
+            .collect::<Result<_, _>>()?,
+        Some(vertex_materials) => {
+            return Err(AudioMeshError::UnsupportedMaterialIndexFormat(
+                vertex_materials.into(),
+            ))
+        }
+        None => triangles.iter().map(|_| 0).collect(),
+    };
+
+    let materials = materials.iter().map(Into::into).collect();
 
     Ok(AudioMesh {
         vertices,
@@ -70,3 +152,294 @@ pub fn try_from(mesh: &Mesh, material: &PhononMaterial) -> Result<AudioMesh, Aud
         material_indices,
     })
 }
+
+/// Drops triangles that reuse a vertex or have (almost) no area, they can not be hit by rays.
+fn remove_degenerate_triangles(
+    triangles: Vec<[u32; 3]>,
+    vertices: &[[f32; 3]],
+) -> Result<Vec<[u32; 3]>, AudioMeshError> {
+    let (min, max) = vertices.iter().map(|vertex| Vec3::from(*vertex)).fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(min, max), vertex| (min.min(vertex), max.max(vertex)),
+    );
+    let min_area = (max - min).length_squared() * MIN_RELATIVE_TRIANGLE_AREA;
+
+    let mut kept = Vec::with_capacity(triangles.len());
+
+    for triangle in triangles {
+        for index in triangle {
+            if index as usize >= vertices.len() {
+                return Err(AudioMeshError::IndexOutOfRange {
+                    index,
+                    num_vertices: vertices.len(),
+                });
+            }
+        }
+
+        let [a, b, c] = triangle.map(|index| Vec3::from(vertices[index as usize]));
+        let double_area = (b - a).cross(c - a).length();
+
+        if double_area * 0.5 > min_area {
+            kept.push(triangle);
+        }
+    }
+
+    if kept.is_empty() {
+        return Err(AudioMeshError::NoTriangles);
+    }
+
+    Ok(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phonon_mesh::material::materials;
+    use bevy::prelude::*;
+    use bevy::render::render_asset::RenderAssetUsages;
+
+    fn convert(mesh: impl Into<Mesh>) -> Result<AudioMesh, AudioMeshError> {
+        try_from(&mesh.into(), &[PhononMaterial::default()])
+    }
+
+    fn mesh(
+        topology: PrimitiveTopology,
+        positions: Vec<[f32; 3]>,
+        indices: Option<Indices>,
+    ) -> Mesh {
+        let mut mesh = Mesh::new(topology, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        if let Some(indices) = indices {
+            mesh.insert_indices(indices);
+        }
+        mesh
+    }
+
+    fn assert_valid(audio_mesh: &AudioMesh) {
+        assert!(!audio_mesh.triangles.is_empty());
+        assert_eq!(
+            audio_mesh.triangles.len(),
+            audio_mesh.material_indices.len()
+        );
+        assert!(audio_mesh
+            .triangles
+            .iter()
+            .flatten()
+            .all(|index| (*index as usize) < audio_mesh.vertices.len()));
+    }
+
+    #[test]
+    fn primitives() {
+        let cuboid = convert(Cuboid::default()).unwrap();
+        assert_valid(&cuboid);
+        assert_eq!(cuboid.triangles.len(), 12);
+
+        let plane = convert(Plane3d::default()).unwrap();
+        assert_valid(&plane);
+        assert_eq!(plane.triangles.len(), 2);
+
+        assert_valid(&convert(Sphere::default()).unwrap());
+        assert_valid(&convert(Torus::default()).unwrap());
+        assert_valid(&convert(Capsule3d::default()).unwrap());
+    }
+
+    #[test]
+    fn non_indexed() {
+        let positions = vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [2.0, 1.0, 0.0],
+            [1.0, 2.0, 0.0],
+        ];
+        let audio_mesh = convert(mesh(PrimitiveTopology::TriangleList, positions, None)).unwrap();
+
+        assert_eq!(audio_mesh.triangles, vec![[0, 1, 2], [3, 4, 5]]);
+    }
+
+    #[test]
+    fn strip_restart() {
+        let positions = vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [1.0, 0.0, 1.0],
+            [0.0, 1.0, 1.0],
+        ];
+        let indices = Indices::U16(vec![0, 1, 2, 3, u16::MAX, 4, 5, 6]);
+        let audio_mesh = convert(mesh(
+            PrimitiveTopology::TriangleStrip,
+            positions,
+            Some(indices),
+        ))
+        .unwrap();
+
+        // The second triangle of the first strip is wound the other way around.
+        assert_eq!(audio_mesh.triangles, vec![[0, 1, 2], [2, 1, 3], [4, 5, 6]]);
+    }
+
+    #[test]
+    fn list_uses_max_u16_index() {
+        let mut positions = vec![[0.0; 3]; u16::MAX as usize + 1];
+        positions[u16::MAX as usize - 2] = [0.0, 0.0, 0.0];
+        positions[u16::MAX as usize - 1] = [1.0, 0.0, 0.0];
+        positions[u16::MAX as usize] = [0.0, 1.0, 0.0];
+        let indices = Indices::U16(vec![u16::MAX - 2, u16::MAX - 1, u16::MAX]);
+        let audio_mesh = convert(mesh(
+            PrimitiveTopology::TriangleList,
+            positions,
+            Some(indices),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            audio_mesh.triangles,
+            vec![[u16::MAX as u32 - 2, u16::MAX as u32 - 1, u16::MAX as u32]]
+        );
+    }
+
+    #[test]
+    fn degenerate_triangles() {
+        let positions = vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [2.0, 0.0, 0.0],
+        ];
+        // A repeated vertex and three points on a line.
+        let degenerate = Indices::U32(vec![0, 0, 1, 0, 1, 3]);
+        let result = convert(mesh(
+            PrimitiveTopology::TriangleList,
+            positions.clone(),
+            Some(degenerate),
+        ));
+        assert!(matches!(result, Err(AudioMeshError::NoTriangles)));
+
+        let mixed = Indices::U32(vec![0, 0, 1, 0, 1, 2]);
+        let audio_mesh = convert(mesh(
+            PrimitiveTopology::TriangleList,
+            positions,
+            Some(mixed),
+        ))
+        .unwrap();
+        assert_eq!(audio_mesh.triangles, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn small_meshes_keep_their_triangles() {
+        // A micrometer sized triangle, far below a fixed area limit in square meters.
+        let positions = vec![[0.0, 0.0, 0.0], [1e-6, 0.0, 0.0], [0.0, 1e-6, 0.0]];
+        let audio_mesh = convert(mesh(PrimitiveTopology::TriangleList, positions, None)).unwrap();
+        assert_eq!(audio_mesh.triangles, vec![[0, 1, 2]]);
+
+        // Slivers are still dropped at that scale.
+        let positions = vec![
+            [0.0, 0.0, 0.0],
+            [1e-6, 0.0, 0.0],
+            [0.0, 1e-6, 0.0],
+            [1e-6, 1e-18, 0.0],
+        ];
+        let indices = Indices::U32(vec![0, 1, 2, 0, 3, 1]);
+        let audio_mesh = convert(mesh(
+            PrimitiveTopology::TriangleList,
+            positions,
+            Some(indices),
+        ))
+        .unwrap();
+        assert_eq!(audio_mesh.triangles, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn invalid_meshes() {
+        let positions = vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+        let out_of_range = Indices::U32(vec![0, 1, 3]);
+        let result = convert(mesh(
+            PrimitiveTopology::TriangleList,
+            positions.clone(),
+            Some(out_of_range),
+        ));
+        assert!(matches!(
+            result,
+            Err(AudioMeshError::IndexOutOfRange { index: 3, .. })
+        ));
+
+        let lines = mesh(PrimitiveTopology::LineList, positions.clone(), None);
+        assert!(matches!(
+            convert(lines),
+            Err(AudioMeshError::NonTrianglePrimitiveTopology(_))
+        ));
+
+        let triangle = mesh(PrimitiveTopology::TriangleList, positions.clone(), None);
+        assert!(matches!(
+            try_from(&triangle, &[]),
+            Err(AudioMeshError::NoMaterials)
+        ));
+
+        // The triangle takes the material of vertex 1, which has none.
+        let short_materials = mesh(
+            PrimitiveTopology::TriangleList,
+            positions.clone(),
+            Some(Indices::U32(vec![1, 2, 0])),
+        )
+        .with_inserted_attribute(ATTRIBUTE_PHONON_MATERIAL_INDEX, vec![0u32]);
+        assert!(matches!(
+            convert(short_materials),
+            Err(AudioMeshError::MissingMaterialIndex { vertex: 1, .. })
+        ));
+
+        let unknown_material = triangle
+            .clone()
+            .with_inserted_attribute(ATTRIBUTE_PHONON_MATERIAL_INDEX, vec![1u32; 3]);
+        assert!(matches!(
+            convert(unknown_material),
+            Err(AudioMeshError::MaterialIndexOutOfRange { index: 1, .. })
+        ));
+
+        // Attributes are looked up by id, so a wrong format is not caught by Bevy.
+        let mut wrong_format = triangle;
+        wrong_format.insert_attribute(
+            ATTRIBUTE_PHONON_MATERIAL_INDEX,
+            VertexAttributeValues::Uint16x2(vec![[0, 0]; 3]),
+        );
+        assert!(matches!(
+            convert(wrong_format),
+            Err(AudioMeshError::UnsupportedMaterialIndexFormat(
+                VertexFormat::Uint16x2
+            ))
+        ));
+    }
+
+    #[test]
+    fn palette_materials_per_triangle() {
+        let positions = vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+        ];
+        let quad = mesh(
+            PrimitiveTopology::TriangleList,
+            positions,
+            Some(Indices::U32(vec![0, 1, 2, 3, 2, 1])),
+        )
+        .with_inserted_attribute(ATTRIBUTE_PHONON_MATERIAL_INDEX, vec![0u32, 0, 0, 1]);
+        let palette = [materials::CONCRETE, materials::GLASS];
+        let audio_mesh = try_from(&quad, &palette).unwrap();
+
+        // Each triangle takes the material of its first vertex.
+        assert_eq!(audio_mesh.material_indices, vec![0, 1]);
+        assert_eq!(audio_mesh.materials.len(), 2);
+
+        // Without the attribute everything is made of the first material.
+        let mut plain = quad;
+        plain.remove_attribute(ATTRIBUTE_PHONON_MATERIAL_INDEX);
+        assert_eq!(
+            try_from(&plain, &palette).unwrap().material_indices,
+            vec![0, 0]
+        );
+    }
+}
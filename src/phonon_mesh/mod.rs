@@ -1,44 +1,986 @@
 pub(crate) mod instancing;
 pub(crate) mod material;
-mod mesh;
+#[cfg(feature = "gltf")]
+pub(crate) mod material_map;
+pub(crate) mod mesh;
 
-use crate::phonon_mesh::instancing::MeshParam;
+use crate::error::PhononError;
+use crate::phonon_mesh::instancing::{MeshParam, StaticMeshKey, StaticMeshes};
+use crate::phonon_mesh::material::{AudioMaterial, PhononMaterial};
+use crate::phonon_plugin::SteamSimulation;
+use bevy::asset::LoadState;
 use bevy::prelude::*;
-use steamaudio::scene::InstancedMesh;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use steamaudio::scene::{InstancedMesh, StaticMesh};
 
-#[derive(Component, Default)]
-pub struct NeedsAudioMesh(pub material::PhononMaterial);
+/// Requests audio geometry for the Bevy mesh of this entity, made of the given material.
+/// If the entity has a `PhononMaterialPalette`, the palette replaces this material completely,
+/// the material is only used again once the palette is removed.
+/// A material asset is waited for until it is loaded.
+/// The component stays on the entity, changing it rebuilds the geometry and removing it
+/// removes the geometry. This way it can be saved in scenes.
+/// Only `Reflect` like `AudioMaterial`, see there.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component, Default)]
+pub struct NeedsAudioMesh(pub AudioMaterial);
 
+impl NeedsAudioMesh {
+    /// Accepts a `PhononMaterial` as well as a `Handle<PhononMaterial>`.
+    pub fn new(material: impl Into<AudioMaterial>) -> Self {
+        Self(material.into())
+    }
+}
+
+/// Materials for different parts of one mesh, selected per triangle with
+/// `ATTRIBUTE_PHONON_MATERIAL_INDEX`. For example glass windows, concrete walls
+/// and carpet floors in a single building mesh.
+/// Takes precedence over the material of `NeedsAudioMesh`. Adding, changing or removing
+/// the palette rebuilds the geometry, and tries an `AudioMeshFailed` mesh again.
+#[derive(Component, Reflect, Debug, Clone, Default, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct PhononMaterialPalette(pub Vec<PhononMaterial>);
+
+/// Whether the audio geometry of an entity with `NeedsAudioMesh` can move.
+/// Without this component the geometry is `Dynamic`.
+#[derive(
+    Component, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize,
+)]
+#[reflect(Component, Default)]
+pub enum PhononMeshMobility {
+    /// Baked into the scene in world space once. Much cheaper, but later changes
+    /// to the transform are ignored.
+    Static,
+    /// Follows the `GlobalTransform` of the entity.
+    #[default]
+    Dynamic,
+}
+
+/// Hides or shows the audio geometry of an entity without destroying it.
+/// Without this component the geometry is enabled.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[reflect(Component)]
+pub struct AudioGeometryEnabled(pub bool);
+
+/// Marks an entity with `NeedsAudioMesh` whose mesh could not be converted, the reason is
+/// sent as a `PhononError` event. Remove this component to try again.
+#[derive(Component, Reflect, Debug, Default, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct AudioMeshFailed;
+
+/// Marks an entity whose mesh has been added to the Steam Audio scene.
+/// The Steam Audio geometry itself lives in `AudioMeshes`, so it can still be removed
+/// after the entity has been despawned.
 #[derive(Component)]
-pub(crate) struct PhononMesh(InstancedMesh);
+pub(crate) struct PhononMesh;
+
+pub(crate) enum AudioGeometry {
+    Instanced(InstancedMesh),
+    Static(StaticMesh),
+}
+
+pub(crate) struct RegisteredMesh {
+    geometry: AudioGeometry,
+    /// The Bevy mesh and materials this was converted from.
+    /// For instanced geometry also the cached sub scene it is an instance of.
+    key: StaticMeshKey,
+    /// What the entity asked for, to find the geometry made of a material asset that changes.
+    requested: AudioMaterial,
+}
+
+impl RegisteredMesh {
+    fn set_visible(&mut self, visible: bool) {
+        match &mut self.geometry {
+            AudioGeometry::Instanced(instanced_mesh) => instanced_mesh.set_visible(visible),
+            AudioGeometry::Static(static_mesh) => static_mesh.set_visible(visible),
+        }
+    }
+}
+
+/// All geometry that is currently part of the Steam Audio scene, by entity.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub(crate) struct AudioMeshes(HashMap<Entity, RegisteredMesh>);
 
 /// If an entity with a `NeedsAudioMesh` marker and a Bevy mesh exist, it will attempt to convert
 /// the mesh to a Steam Audio mesh and add it to the audio world.
+/// Meshes that are still loading are tried again every frame, registered entities only
+/// when their `NeedsAudioMesh`, `PhononMaterialPalette`, `PhononMeshMobility` or Bevy mesh
+/// changes.
+/// Conversion errors are sent as `PhononError` events and the entity is marked `AudioMeshFailed`,
+/// without any geometry, also if it had some before the change.
 pub(crate) fn register_audio_meshes(
     mut commands: Commands,
     mut mesh_param: MeshParam,
-    mut object_query: Query<(Entity, &Handle<Mesh>, &NeedsAudioMesh)>,
+    mut audio_meshes: ResMut<AudioMeshes>,
+    phonon_materials: Res<Assets<PhononMaterial>>,
+    asset_server: Option<Res<AssetServer>>,
+    mut object_query: Query<
+        (
+            Entity,
+            &Handle<Mesh>,
+            &NeedsAudioMesh,
+            Option<&PhononMaterialPalette>,
+            Option<&PhononMeshMobility>,
+            Option<&AudioGeometryEnabled>,
+            &GlobalTransform,
+        ),
+        (
+            Without<AudioMeshFailed>,
+            Or<(
+                Without<PhononMesh>,
+                Changed<NeedsAudioMesh>,
+                Changed<Handle<Mesh>>,
+                Changed<PhononMaterialPalette>,
+                Changed<PhononMeshMobility>,
+            )>,
+        ),
+    >,
+    mut errors: EventWriter<PhononError>,
 ) {
-    for (ent, mesh_handle, requested_material) in &mut object_query {
-        let mut instanced_mesh = mesh_param
-            .create_instanced_mesh(mesh_handle, &requested_material.0)
-            .unwrap();
-        instanced_mesh.set_visible(true);
+    let scene_lock = mesh_param.simulator.scene_lock.clone();
+    let _scene = scene_lock.lock();
+
+    for (ent, mesh_handle, requested_material, palette, mobility, enabled, transform) in
+        &mut object_query
+    {
+        let load_failed = |id| {
+            asset_server.as_ref().is_some_and(|asset_server| {
+                matches!(asset_server.load_state(id), LoadState::Failed)
+            })
+        };
+
+        if !mesh_param.bevy_meshes.contains(mesh_handle) {
+            // Otherwise the mesh is still loading, for example from a glTF file.
+            if load_failed(mesh_handle.id().untyped()) {
+                errors.send(PhononError::MeshLoadFailed(ent));
+                mark_failed(ent, &mut commands, &mut mesh_param, &mut audio_meshes);
+            }
+            continue;
+        }
+
+        let materials = match (palette, &requested_material.0) {
+            (Some(palette), _) => palette.0.clone(),
+            (None, AudioMaterial::Inline(material)) => vec![material.clone()],
+            (None, AudioMaterial::Asset(handle)) => match phonon_materials.get(handle) {
+                Some(material) => vec![material.clone()],
+                None => {
+                    if load_failed(handle.id().untyped()) {
+                        errors.send(PhononError::MaterialLoadFailed(ent));
+                        mark_failed(ent, &mut commands, &mut mesh_param, &mut audio_meshes);
+                    }
+                    continue;
+                }
+            },
+        };
+
+        let mobility = mobility.copied().unwrap_or_default();
+
+        let registered_mesh = match mobility {
+            PhononMeshMobility::Static => mesh_param
+                .create_static_mesh(ent, mesh_handle, &materials, transform)
+                .map(|static_mesh| RegisteredMesh {
+                    geometry: AudioGeometry::Static(static_mesh),
+                    key: (mesh_handle.clone(), materials),
+                    requested: requested_material.0.clone(),
+                }),
+            PhononMeshMobility::Dynamic => mesh_param
+                .create_instanced_mesh(ent, mesh_handle, &materials)
+                .map(|mut instanced_mesh| {
+                    instanced_mesh.set_transform(transform.compute_matrix());
+                    RegisteredMesh {
+                        geometry: AudioGeometry::Instanced(instanced_mesh),
+                        key: (mesh_handle.clone(), materials),
+                        requested: requested_material.0.clone(),
+                    }
+                }),
+        };
 
-        let scene_root = &mesh_param.simulator.scene;
-        scene_root.commit();
+        match registered_mesh {
+            Ok(mut registered_mesh) => {
+                registered_mesh.set_visible(enabled.map_or(true, |enabled| enabled.0));
+                mesh_param.simulator.scene_dirty = true;
 
-        commands.entity(ent).insert(PhononMesh(instanced_mesh));
-        commands.entity(ent).remove::<NeedsAudioMesh>();
+                // The entity might have been registered before with a different mesh.
+                if let Some(previous) = audio_meshes.insert(ent, registered_mesh) {
+                    remove_registered_mesh(
+                        previous,
+                        &mut mesh_param.simulator,
+                        &mut mesh_param.static_meshes,
+                    );
+                }
+
+                commands.entity(ent).insert(PhononMesh);
+            }
+            Err(error) => {
+                errors.send(error);
+                mark_failed(ent, &mut commands, &mut mesh_param, &mut audio_meshes);
+            }
+        }
+    }
+}
+
+/// Marks the entity `AudioMeshFailed` and removes the geometry it had before, which was made
+/// of the mesh or materials the entity has been changed away from.
+fn mark_failed(
+    ent: Entity,
+    commands: &mut Commands,
+    mesh_param: &mut MeshParam,
+    audio_meshes: &mut AudioMeshes,
+) {
+    if let Some(previous) = audio_meshes.remove(&ent) {
+        remove_registered_mesh(
+            previous,
+            &mut mesh_param.simulator,
+            &mut mesh_param.static_meshes,
+        );
     }
+
+    commands
+        .entity(ent)
+        .insert(AudioMeshFailed)
+        .remove::<PhononMesh>();
 }
 
-//Changed<GlobalTransform> or Changed Mesh? not worth it probably
+/// Only dynamic geometry follows its transform, static geometry is already in world space.
 pub(crate) fn update_audio_mesh_transforms(
-    mut object_query: Query<(&GlobalTransform, &mut PhononMesh)>,
+    mut sim_res: ResMut<SteamSimulation>,
+    mut audio_meshes: ResMut<AudioMeshes>,
+    object_query: Query<(Entity, &GlobalTransform), (With<PhononMesh>, Changed<GlobalTransform>)>,
+) {
+    let scene_lock = sim_res.scene_lock.clone();
+    let _scene = scene_lock.lock();
+
+    for (ent, transform) in &object_query {
+        if let Some(RegisteredMesh {
+            geometry: AudioGeometry::Instanced(instanced_mesh),
+            ..
+        }) = audio_meshes.get_mut(&ent)
+        {
+            instanced_mesh.set_transform(transform.compute_matrix());
+            sim_res.scene_dirty = true;
+        }
+    }
+}
+
+/// Applies `AudioGeometryEnabled`, removing the component enables the geometry again.
+pub(crate) fn update_audio_geometry_enabled(
+    mut sim_res: ResMut<SteamSimulation>,
+    mut audio_meshes: ResMut<AudioMeshes>,
+    enabled_query: Query<(Entity, &AudioGeometryEnabled), Changed<AudioGeometryEnabled>>,
+    mut removed_enabled: RemovedComponents<AudioGeometryEnabled>,
+) {
+    let scene_lock = sim_res.scene_lock.clone();
+    let _scene = scene_lock.lock();
+
+    let changes = enabled_query
+        .iter()
+        .map(|(ent, enabled)| (ent, enabled.0))
+        .chain(removed_enabled.read().map(|ent| (ent, true)))
+        .collect::<Vec<_>>();
+
+    for (ent, visible) in changes {
+        if let Some(registered_mesh) = audio_meshes.get_mut(&ent) {
+            registered_mesh.set_visible(visible);
+            sim_res.scene_dirty = true;
+        }
+    }
+}
+
+/// Takes the geometry of entities out of the Steam Audio scene when they are despawned,
+/// lose their Bevy mesh or their `NeedsAudioMesh`.
+pub(crate) fn remove_audio_meshes(
+    mut commands: Commands,
+    mut sim_res: ResMut<SteamSimulation>,
+    mut static_meshes: ResMut<StaticMeshes>,
+    mut audio_meshes: ResMut<AudioMeshes>,
+    mut removed_bevy_meshes: RemovedComponents<Handle<Mesh>>,
+    mut removed_requests: RemovedComponents<NeedsAudioMesh>,
+) {
+    let scene_lock = sim_res.scene_lock.clone();
+    let _scene = scene_lock.lock();
+
+    let removed = removed_bevy_meshes
+        .read()
+        .chain(removed_requests.read())
+        .collect::<Vec<_>>();
+
+    for ent in removed {
+        let Some(registered_mesh) = audio_meshes.remove(&ent) else {
+            continue;
+        };
+
+        remove_registered_mesh(registered_mesh, &mut sim_res, &mut static_meshes);
+
+        // The entity might still exist and get audio geometry again later.
+        if let Some(mut entity_commands) = commands.get_entity(ent) {
+            entity_commands.remove::<PhononMesh>();
+        }
+    }
+}
+
+fn remove_registered_mesh(
+    registered_mesh: RegisteredMesh,
+    sim_res: &mut SteamSimulation,
+    static_meshes: &mut StaticMeshes,
 ) {
-    for (transform, mut audio_instance) in &mut object_query {
-        let instanced_mesh = &mut audio_instance.0;
-        instanced_mesh.set_transform(transform.compute_matrix());
+    match registered_mesh.geometry {
+        AudioGeometry::Instanced(instanced_mesh) => {
+            sim_res.scene.remove_instanced_mesh(&instanced_mesh);
+            static_meshes.release(&registered_mesh.key);
+        }
+        AudioGeometry::Static(static_mesh) => {
+            sim_res.scene.remove_static_mesh(&static_mesh);
+        }
+    }
+
+    sim_res.scene_dirty = true;
+}
+
+/// Keeps the audio geometry in sync with modified (e.g. hot-reloaded) and removed mesh assets.
+/// The cached conversions of the asset are evicted and the geometry of every entity using it
+/// is removed. Those entities lose their `PhononMesh`, so `register_audio_meshes` converts
+/// the new version of the mesh, or waits for a removed one to come back.
+pub(crate) fn handle_mesh_asset_events(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<Mesh>>,
+    mut sim_res: ResMut<SteamSimulation>,
+    mut static_meshes: ResMut<StaticMeshes>,
+    mut audio_meshes: ResMut<AudioMeshes>,
+    failed_query: Query<(Entity, &Handle<Mesh>), With<AudioMeshFailed>>,
+) {
+    let scene_lock = sim_res.scene_lock.clone();
+    let _scene = scene_lock.lock();
+
+    for asset_event in asset_events.read() {
+        let id = match asset_event {
+            AssetEvent::Modified { id } | AssetEvent::Removed { id } => *id,
+            _ => continue,
+        };
+
+        static_meshes.retain(|(mesh_handle, _), _| mesh_handle.id() != id);
+
+        let affected = audio_meshes
+            .iter()
+            .filter(|(_, registered_mesh)| registered_mesh.key.0.id() == id)
+            .map(|(ent, _)| *ent)
+            .collect::<Vec<_>>();
+
+        request_audio_meshes_again(
+            affected,
+            &mut commands,
+            &mut sim_res,
+            &mut static_meshes,
+            &mut audio_meshes,
+        );
+
+        // The new version of the mesh might convert fine.
+        if matches!(asset_event, AssetEvent::Modified { .. }) {
+            for (ent, mesh_handle) in &failed_query {
+                if mesh_handle.id() == id {
+                    commands.entity(ent).remove::<AudioMeshFailed>();
+                }
+            }
+        }
+    }
+}
+
+/// Rebuilds the geometry made from a `PhononMaterial` asset when the file changes, and removes
+/// it when the asset is removed, until the asset comes back.
+/// Meshes marked `AudioMeshFailed` are tried again when their material is added or modified.
+pub(crate) fn handle_material_asset_events(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<PhononMaterial>>,
+    mut sim_res: ResMut<SteamSimulation>,
+    mut static_meshes: ResMut<StaticMeshes>,
+    mut audio_meshes: ResMut<AudioMeshes>,
+    failed_query: Query<(Entity, &NeedsAudioMesh), With<AudioMeshFailed>>,
+) {
+    let scene_lock = sim_res.scene_lock.clone();
+    let _scene = scene_lock.lock();
+
+    for asset_event in asset_events.read() {
+        let (id, rebuild, retry) = match asset_event {
+            AssetEvent::Added { id } => (*id, false, true),
+            AssetEvent::Modified { id } => (*id, true, true),
+            AssetEvent::Removed { id } => (*id, true, false),
+            _ => continue,
+        };
+        let uses_asset = |material: &AudioMaterial| match material {
+            AudioMaterial::Asset(handle) => handle.id() == id,
+            AudioMaterial::Inline(_) => false,
+        };
+
+        if rebuild {
+            let affected = audio_meshes
+                .iter()
+                .filter(|(_, registered_mesh)| uses_asset(&registered_mesh.requested))
+                .map(|(ent, _)| *ent)
+                .collect::<Vec<_>>();
+
+            request_audio_meshes_again(
+                affected,
+                &mut commands,
+                &mut sim_res,
+                &mut static_meshes,
+                &mut audio_meshes,
+            );
+        }
+
+        if retry {
+            for (ent, needs_audio_mesh) in &failed_query {
+                if uses_asset(&needs_audio_mesh.0) {
+                    commands.entity(ent).remove::<AudioMeshFailed>();
+                }
+            }
+        }
+    }
+}
+
+/// Rebuilds the geometry of entities whose `PhononMaterialPalette` was removed, they are made
+/// of their `NeedsAudioMesh` material again. Changed palettes are picked up by
+/// `register_audio_meshes`, except for meshes that failed, which are tried again here.
+pub(crate) fn handle_palette_changes(
+    mut commands: Commands,
+    mut sim_res: ResMut<SteamSimulation>,
+    mut static_meshes: ResMut<StaticMeshes>,
+    mut audio_meshes: ResMut<AudioMeshes>,
+    mut removed_palettes: RemovedComponents<PhononMaterialPalette>,
+    failed_query: Query<Entity, (With<AudioMeshFailed>, Changed<PhononMaterialPalette>)>,
+) {
+    let scene_lock = sim_res.scene_lock.clone();
+    let _scene = scene_lock.lock();
+
+    let removed = removed_palettes.read().collect::<Vec<_>>();
+
+    for ent in removed.iter().copied().chain(&failed_query) {
+        if let Some(mut entity_commands) = commands.get_entity(ent) {
+            entity_commands.remove::<AudioMeshFailed>();
+        }
+    }
+
+    request_audio_meshes_again(
+        removed,
+        &mut commands,
+        &mut sim_res,
+        &mut static_meshes,
+        &mut audio_meshes,
+    );
+}
+
+/// Takes the geometry of the entities out of the scene and removes their `PhononMesh`,
+/// so `register_audio_meshes` converts them again with the current assets.
+/// Their `NeedsAudioMesh` is left alone, it might have been changed this frame.
+fn request_audio_meshes_again(
+    entities: Vec<Entity>,
+    commands: &mut Commands,
+    sim_res: &mut SteamSimulation,
+    static_meshes: &mut StaticMeshes,
+    audio_meshes: &mut AudioMeshes,
+) {
+    for ent in entities {
+        let Some(registered_mesh) = audio_meshes.remove(&ent) else {
+            continue;
+        };
+
+        remove_registered_mesh(registered_mesh, sim_res, static_meshes);
+
+        if let Some(mut entity_commands) = commands.get_entity(ent) {
+            entity_commands.remove::<PhononMesh>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phonon_mesh::material::materials;
+    use crate::phonon_mesh::mesh::{AudioMeshError, ATTRIBUTE_PHONON_MATERIAL_INDEX};
+    use crate::phonon_plugin::headless_app;
+    use crate::phonon_queries::PhononQueries;
+    use crate::settings::PhononSettings;
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::render::mesh::PrimitiveTopology;
+    use bevy::render::render_asset::RenderAssetUsages;
+
+    fn wall(app: &mut App) -> Handle<Mesh> {
+        app.world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Cuboid::new(10.0, 10.0, 0.2))
+    }
+
+    /// Whether a sound at `to` reaches `from` without passing through geometry.
+    fn line_of_sound(app: &mut App, from: Vec3, to: Vec3) -> bool {
+        app.world
+            .run_system_once(move |mut queries: PhononQueries| {
+                queries.is_line_of_sound(from, to).unwrap()
+            })
+    }
+
+    fn spawn_wall(app: &mut App, mesh: Handle<Mesh>, mobility: PhononMeshMobility) -> Entity {
+        app.world
+            .spawn((
+                mesh,
+                NeedsAudioMesh::new(materials::CONCRETE),
+                mobility,
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, -2.5)),
+            ))
+            .id()
+    }
+
+    /// Whether the wall of `spawn_wall` is in the way.
+    fn wall_occludes(app: &mut App) -> bool {
+        !line_of_sound(app, Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, -5.0))
+    }
+
+    /// `PhononError`s sent in the last two updates.
+    fn error_count(app: &App) -> usize {
+        app.world.resource::<Events<PhononError>>().len()
+    }
+
+    /// What the geometry of `ent` was built from.
+    fn materials_of(app: &App, ent: Entity) -> Vec<PhononMaterial> {
+        app.world.resource::<AudioMeshes>()[&ent].key.1.clone()
+    }
+
+    fn sub_scene_users(app: &App) -> Vec<usize> {
+        app.world
+            .resource::<StaticMeshes>()
+            .values()
+            .map(|cached| cached.users)
+            .collect()
+    }
+
+    #[test]
+    fn static_geometry_is_baked_where_it_was_spawned() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        app.world.spawn((
+            mesh,
+            NeedsAudioMesh::new(materials::CONCRETE),
+            PhononMeshMobility::Static,
+            TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, -2.5)),
+        ));
+        app.update();
+
+        let behind_wall = line_of_sound(
+            &mut app,
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, -5.0),
+        );
+        let at_origin = line_of_sound(
+            &mut app,
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        assert!(!behind_wall);
+        assert!(at_origin);
+    }
+
+    #[test]
+    fn despawned_geometry_is_removed() {
+        for mobility in [PhononMeshMobility::Dynamic, PhononMeshMobility::Static] {
+            let mut app = headless_app(PhononSettings::default());
+            let mesh = wall(&mut app);
+            let wall = spawn_wall(&mut app, mesh, mobility);
+            app.update();
+            assert!(wall_occludes(&mut app));
+
+            app.world.despawn(wall);
+            app.update();
+            assert!(!wall_occludes(&mut app));
+            assert!(app.world.resource::<AudioMeshes>().is_empty());
+            assert!(app.world.resource::<StaticMeshes>().is_empty());
+        }
+    }
+
+    #[test]
+    fn geometry_is_removed_with_its_components() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let wall = spawn_wall(&mut app, mesh.clone(), PhononMeshMobility::Dynamic);
+        app.update();
+
+        app.world.entity_mut(wall).remove::<NeedsAudioMesh>();
+        app.update();
+        assert!(!wall_occludes(&mut app));
+        assert!(!app.world.entity(wall).contains::<PhononMesh>());
+
+        app.world
+            .entity_mut(wall)
+            .insert(NeedsAudioMesh::new(materials::CONCRETE));
+        app.update();
+        assert!(wall_occludes(&mut app));
+
+        app.world.entity_mut(wall).remove::<Handle<Mesh>>();
+        app.update();
+        assert!(!wall_occludes(&mut app));
+        assert!(app.world.resource::<AudioMeshes>().is_empty());
+    }
+
+    #[test]
+    fn sub_scenes_are_shared_until_the_last_user_is_gone() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let walls = [
+            spawn_wall(&mut app, mesh.clone(), PhononMeshMobility::Dynamic),
+            spawn_wall(&mut app, mesh.clone(), PhononMeshMobility::Dynamic),
+        ];
+        app.update();
+        assert_eq!(sub_scene_users(&app), [2]);
+
+        app.world.despawn(walls[0]);
+        app.update();
+        assert_eq!(sub_scene_users(&app), [1]);
+        assert!(wall_occludes(&mut app));
+
+        app.world.despawn(walls[1]);
+        app.update();
+        assert!(sub_scene_users(&app).is_empty());
+        assert!(!wall_occludes(&mut app));
+    }
+
+    #[test]
+    fn disabled_geometry_is_hidden() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let wall = spawn_wall(&mut app, mesh, PhononMeshMobility::Dynamic);
+        app.update();
+
+        app.world
+            .entity_mut(wall)
+            .insert(AudioGeometryEnabled(false));
+        app.update();
+        assert!(!wall_occludes(&mut app));
+        assert!(app.world.resource::<AudioMeshes>().contains_key(&wall));
+
+        app.world
+            .entity_mut(wall)
+            .insert(AudioGeometryEnabled(true));
+        app.update();
+        assert!(wall_occludes(&mut app));
+
+        app.world
+            .entity_mut(wall)
+            .insert(AudioGeometryEnabled(false));
+        app.update();
+        app.world.entity_mut(wall).remove::<AudioGeometryEnabled>();
+        app.update();
+        assert!(wall_occludes(&mut app));
+    }
+
+    #[test]
+    fn registration_waits_for_the_mesh_to_load() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = app.world.resource::<Assets<Mesh>>().reserve_handle();
+        let wall = spawn_wall(&mut app, mesh.clone(), PhononMeshMobility::Dynamic);
+
+        for _ in 0..3 {
+            app.update();
+        }
+        assert!(!wall_occludes(&mut app));
+        assert!(app.world.entity(wall).contains::<NeedsAudioMesh>());
+        assert!(!app.world.entity(wall).contains::<AudioMeshFailed>());
+        assert_eq!(error_count(&app), 0);
+
+        app.world
+            .resource_mut::<Assets<Mesh>>()
+            .insert(mesh.id(), Cuboid::new(10.0, 10.0, 0.2).into());
+        app.update();
+        assert!(wall_occludes(&mut app));
+    }
+
+    #[test]
+    fn registration_waits_for_the_material_to_load() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let material = app
+            .world
+            .resource::<Assets<PhononMaterial>>()
+            .reserve_handle();
+        let wall = spawn_wall(&mut app, mesh, PhononMeshMobility::Dynamic);
+        app.world
+            .entity_mut(wall)
+            .insert(NeedsAudioMesh::new(material.clone()));
+
+        app.update();
+        app.update();
+        assert!(!wall_occludes(&mut app));
+
+        app.world
+            .resource_mut::<Assets<PhononMaterial>>()
+            .insert(material.id(), materials::CONCRETE);
+        app.update();
+        assert!(wall_occludes(&mut app));
+    }
+
+    #[test]
+    fn conversion_errors_are_reported() {
+        let mut app = headless_app(PhononSettings::default());
+        let lines = Mesh::new(PrimitiveTopology::LineList, RenderAssetUsages::default())
+            .with_inserted_attribute(
+                Mesh::ATTRIBUTE_POSITION,
+                vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]],
+            );
+        let mesh = app.world.resource_mut::<Assets<Mesh>>().add(lines);
+        let wall = spawn_wall(&mut app, mesh, PhononMeshMobility::Dynamic);
+        app.update();
+
+        let events = app.world.resource::<Events<PhononError>>();
+        let mut reader = events.get_reader();
+        let errors = reader.read(events).collect::<Vec<_>>();
+        assert!(matches!(
+            errors[..],
+            [PhononError::AudioMesh {
+                entity,
+                error: AudioMeshError::NonTrianglePrimitiveTopology(PrimitiveTopology::LineList),
+            }] if *entity == wall
+        ));
+        assert!(app.world.entity(wall).contains::<AudioMeshFailed>());
+
+        // Not tried again every frame.
+        app.world.resource_mut::<Events<PhononError>>().clear();
+        app.update();
+        assert_eq!(error_count(&app), 0);
+    }
+
+    #[test]
+    fn failed_rebuilds_remove_the_old_geometry() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let wall = spawn_wall(&mut app, mesh, PhononMeshMobility::Dynamic);
+        app.update();
+        assert!(wall_occludes(&mut app));
+
+        app.world
+            .entity_mut(wall)
+            .insert(PhononMaterialPalette(Vec::new()));
+        app.update();
+        assert!(!wall_occludes(&mut app));
+        assert!(app.world.resource::<AudioMeshes>().is_empty());
+        assert!(sub_scene_users(&app).is_empty());
+        assert!(app.world.entity(wall).contains::<AudioMeshFailed>());
+        assert!(!app.world.entity(wall).contains::<PhononMesh>());
+    }
+
+    #[test]
+    fn modified_meshes_are_rebuilt() {
+        for mobility in [PhononMeshMobility::Dynamic, PhononMeshMobility::Static] {
+            let mut app = headless_app(PhononSettings::default());
+            let mesh = wall(&mut app);
+            let wall = spawn_wall(&mut app, mesh.clone(), mobility);
+            app.update();
+            assert!(wall_occludes(&mut app));
+
+            // Shrunk to a pillar next to the line of sound.
+            let pillar = Mesh::from(Cuboid::new(0.2, 10.0, 0.2)).translated_by(Vec3::X * 2.0);
+            app.world
+                .resource_mut::<Assets<Mesh>>()
+                .insert(mesh.id(), pillar);
+            app.update();
+            assert!(!wall_occludes(&mut app));
+            assert!(app.world.resource::<AudioMeshes>().contains_key(&wall));
+
+            if mobility == PhononMeshMobility::Dynamic {
+                assert_eq!(sub_scene_users(&app), [1]);
+            }
+        }
+    }
+
+    #[test]
+    fn removed_meshes_are_evicted() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let wall = spawn_wall(&mut app, mesh.clone(), PhononMeshMobility::Dynamic);
+        app.update();
+
+        app.world.resource_mut::<Assets<Mesh>>().remove(mesh.id());
+        app.update();
+        assert!(!wall_occludes(&mut app));
+        assert!(app.world.resource::<AudioMeshes>().is_empty());
+        assert!(sub_scene_users(&app).is_empty());
+        // Waits for the mesh to come back.
+        assert!(app.world.entity(wall).contains::<NeedsAudioMesh>());
+        assert!(!app.world.entity(wall).contains::<AudioMeshFailed>());
+
+        app.world
+            .resource_mut::<Assets<Mesh>>()
+            .insert(mesh.id(), Cuboid::new(10.0, 10.0, 0.2).into());
+        app.update();
+        assert!(wall_occludes(&mut app));
+    }
+
+    #[test]
+    fn rebuilds_keep_requests_of_the_same_frame() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let wall = spawn_wall(&mut app, mesh.clone(), PhononMeshMobility::Dynamic);
+        app.update();
+
+        app.world
+            .resource_mut::<Assets<Mesh>>()
+            .insert(mesh.id(), Cuboid::new(10.0, 10.0, 0.4).into());
+        app.world
+            .entity_mut(wall)
+            .insert(NeedsAudioMesh::new(materials::GLASS));
+        app.update();
+        assert_eq!(materials_of(&app, wall), [materials::GLASS]);
+        assert!(matches!(
+            app.world.get::<NeedsAudioMesh>(wall),
+            Some(NeedsAudioMesh(AudioMaterial::Inline(material))) if *material == materials::GLASS
+        ));
+    }
+
+    #[test]
+    fn fixed_meshes_are_tried_again() {
+        let mut app = headless_app(PhononSettings::default());
+        let lines = Mesh::new(PrimitiveTopology::LineList, RenderAssetUsages::default());
+        let mesh = app.world.resource_mut::<Assets<Mesh>>().add(lines);
+        let wall = spawn_wall(&mut app, mesh.clone(), PhononMeshMobility::Dynamic);
+        app.update();
+        assert!(app.world.entity(wall).contains::<AudioMeshFailed>());
+
+        app.world
+            .resource_mut::<Assets<Mesh>>()
+            .insert(mesh.id(), Cuboid::new(10.0, 10.0, 0.2).into());
+        app.update();
+        app.update();
+        assert!(!app.world.entity(wall).contains::<AudioMeshFailed>());
+        assert!(wall_occludes(&mut app));
+    }
+
+    #[test]
+    fn palettes_replace_the_material() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let wall = spawn_wall(&mut app, mesh, PhononMeshMobility::Dynamic);
+        app.world
+            .entity_mut(wall)
+            .insert(PhononMaterialPalette(vec![
+                materials::GLASS,
+                materials::WOOD,
+            ]));
+        app.update();
+        assert_eq!(
+            materials_of(&app, wall),
+            [materials::GLASS, materials::WOOD]
+        );
+
+        app.world
+            .get_mut::<PhononMaterialPalette>(wall)
+            .unwrap()
+            .0
+            .pop();
+        app.update();
+        assert_eq!(materials_of(&app, wall), [materials::GLASS]);
+
+        app.world.entity_mut(wall).remove::<PhononMaterialPalette>();
+        app.update();
+        assert_eq!(materials_of(&app, wall), [materials::CONCRETE]);
+        assert!(wall_occludes(&mut app));
+    }
+
+    #[test]
+    fn fixed_palettes_are_tried_again() {
+        let mut app = headless_app(PhononSettings::default());
+        let cuboid = Mesh::from(Cuboid::new(10.0, 10.0, 0.2));
+        let second_material = vec![1u32; cuboid.count_vertices()];
+        let mesh = app
+            .world
+            .resource_mut::<Assets<Mesh>>()
+            .add(cuboid.with_inserted_attribute(ATTRIBUTE_PHONON_MATERIAL_INDEX, second_material));
+        let wall = spawn_wall(&mut app, mesh, PhononMeshMobility::Dynamic);
+        app.world
+            .entity_mut(wall)
+            .insert(PhononMaterialPalette(vec![materials::GLASS]));
+        app.update();
+        assert!(app.world.entity(wall).contains::<AudioMeshFailed>());
+
+        app.world
+            .get_mut::<PhononMaterialPalette>(wall)
+            .unwrap()
+            .0
+            .push(materials::WOOD);
+        app.update();
+        assert!(!app.world.entity(wall).contains::<AudioMeshFailed>());
+        assert_eq!(
+            materials_of(&app, wall),
+            [materials::GLASS, materials::WOOD]
+        );
+        assert!(wall_occludes(&mut app));
+    }
+
+    #[test]
+    fn modified_materials_are_rebuilt() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let material = app
+            .world
+            .resource_mut::<Assets<PhononMaterial>>()
+            .add(materials::CONCRETE);
+        let wall = spawn_wall(&mut app, mesh, PhononMeshMobility::Dynamic);
+        app.world
+            .entity_mut(wall)
+            .insert(NeedsAudioMesh::new(material.clone()));
+        app.update();
+        assert_eq!(materials_of(&app, wall), [materials::CONCRETE]);
+
+        // What a hot reload of the file does.
+        app.world
+            .resource_mut::<Assets<PhononMaterial>>()
+            .insert(material.id(), materials::GLASS);
+        app.update();
+        assert_eq!(materials_of(&app, wall), [materials::GLASS]);
+    }
+
+    #[test]
+    fn removed_materials_are_evicted() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let material = app
+            .world
+            .resource_mut::<Assets<PhononMaterial>>()
+            .add(materials::CONCRETE);
+        let wall = spawn_wall(&mut app, mesh, PhononMeshMobility::Dynamic);
+        app.world
+            .entity_mut(wall)
+            .insert(NeedsAudioMesh::new(material.clone()));
+        app.update();
+
+        app.world
+            .resource_mut::<Assets<PhononMaterial>>()
+            .remove(material.id());
+        app.update();
+        assert!(!wall_occludes(&mut app));
+        assert!(app.world.resource::<AudioMeshes>().is_empty());
+
+        app.world
+            .resource_mut::<Assets<PhononMaterial>>()
+            .insert(material.id(), materials::WOOD);
+        app.update();
+        assert_eq!(materials_of(&app, wall), [materials::WOOD]);
+    }
+
+    #[test]
+    fn failed_materials_are_tried_again_when_added() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = wall(&mut app);
+        let material = app
+            .world
+            .resource::<Assets<PhononMaterial>>()
+            .reserve_handle();
+        let wall = spawn_wall(&mut app, mesh, PhononMeshMobility::Dynamic);
+        // As if the file failed to load.
+        app.world
+            .entity_mut(wall)
+            .insert((NeedsAudioMesh::new(material.clone()), AudioMeshFailed));
+        app.update();
+
+        app.world
+            .resource_mut::<Assets<PhononMaterial>>()
+            .insert(material.id(), materials::WOOD);
+        app.update();
+        app.update();
+        assert!(!app.world.entity(wall).contains::<AudioMeshFailed>());
+        assert_eq!(materials_of(&app, wall), [materials::WOOD]);
     }
 }
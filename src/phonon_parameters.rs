@@ -0,0 +1,387 @@
+use crate::error::PhononError;
+use crate::phonon_source::PhononSourceOutputs;
+use bevy::prelude::*;
+use bevy_fmod::prelude::{AudioSource, FmodStudio};
+use serde::{Deserialize, Serialize};
+
+/// Drives FMOD parameters with the simulation results of this source, so FMOD Studio can
+/// react to the acoustics, e.g. muffle music when it is heavily occluded.
+/// Applied after the simulation, requires a registered source with `PhononSourceOutputs`.
+#[derive(Component, Reflect, Debug, Clone, Default, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct PhononParameterBinding(pub Vec<ParameterMapping>);
+
+/// Maps one simulation output to one FMOD parameter.
+#[derive(Reflect, Debug, Clone, Serialize, Deserialize)]
+pub struct ParameterMapping {
+    pub output: SimulationOutput,
+    pub target: ParameterTarget,
+    pub curve: ParameterCurve,
+    /// Seconds it takes to get about two thirds of the way to a new value, 0 applies
+    /// changes immediately.
+    pub smoothing: f32,
+    /// The smoothed value, `None` until the first one.
+    #[reflect(ignore)]
+    #[serde(skip)]
+    value: Option<f32>,
+    /// Last value sent to FMOD.
+    #[reflect(ignore)]
+    #[serde(skip)]
+    sent: Option<f32>,
+    /// Frames to wait before trying a parameter again that FMOD rejected.
+    #[reflect(ignore)]
+    #[serde(skip)]
+    frames_until_retry: u32,
+    /// The current error has been sent, it is sent again only after a success.
+    #[reflect(ignore)]
+    #[serde(skip)]
+    reported: bool,
+}
+
+impl ParameterMapping {
+    pub fn new(output: SimulationOutput, target: ParameterTarget) -> Self {
+        Self {
+            output,
+            target,
+            curve: ParameterCurve::default(),
+            smoothing: 0.0,
+            value: None,
+            sent: None,
+            frames_until_retry: 0,
+            reported: false,
+        }
+    }
+
+    pub fn with_curve(mut self, curve: ParameterCurve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    pub fn with_smoothing(mut self, seconds: f32) -> Self {
+        self.smoothing = seconds;
+        self
+    }
+}
+
+/// Which value of `PhononSourceOutputs` drives the parameter.
+/// Bands are 0 for 400 Hz, 1 for 2.5 KHz and 2 for 15 KHz, averaged where there is none.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SimulationOutput {
+    /// 1 is not occluded at all.
+    Occlusion,
+    Transmission {
+        band: usize,
+    },
+    DistanceAttenuation,
+    AirAbsorption {
+        band: usize,
+    },
+    /// See `PhononSourceOutputs::gain`.
+    Gain,
+    /// Average reverb time in seconds, requires reflections.
+    ReverbTime,
+}
+
+impl SimulationOutput {
+    /// `None` for a band other than 0, 1 or 2.
+    fn read(&self, outputs: &PhononSourceOutputs) -> Option<f32> {
+        let average = |bands: [f32; 3]| bands.iter().sum::<f32>() / 3.0;
+
+        match *self {
+            SimulationOutput::Occlusion => Some(outputs.occlusion),
+            SimulationOutput::Transmission { band } => outputs.transmission.get(band).copied(),
+            SimulationOutput::DistanceAttenuation => Some(outputs.distance_attenuation),
+            SimulationOutput::AirAbsorption { band } => outputs.air_absorption.get(band).copied(),
+            SimulationOutput::Gain => Some(outputs.gain()),
+            SimulationOutput::ReverbTime => Some(average(outputs.reverb_time)),
+        }
+    }
+}
+
+#[derive(Reflect, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ParameterTarget {
+    /// A parameter of the event of the `AudioSource` on this entity.
+    Event(String),
+    /// A global parameter of the FMOD Studio system.
+    Global(String),
+}
+
+impl ParameterTarget {
+    fn name(&self) -> &str {
+        match self {
+            ParameterTarget::Event(name) | ParameterTarget::Global(name) => name,
+        }
+    }
+}
+
+/// Turns a simulation output into a parameter value.
+#[derive(Reflect, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ParameterCurve {
+    /// Maps `input` linearly to `output`, values outside of `input` are clamped.
+    Linear { input: Vec2, output: Vec2 },
+    /// Piecewise linear through `(input, output)` points sorted by input.
+    /// Values before the first or after the last point are clamped.
+    Points(Vec<Vec2>),
+}
+
+impl Default for ParameterCurve {
+    /// Passes the value through unchanged for outputs between 0 and 1.
+    fn default() -> Self {
+        ParameterCurve::Linear {
+            input: Vec2::new(0.0, 1.0),
+            output: Vec2::new(0.0, 1.0),
+        }
+    }
+}
+
+impl ParameterCurve {
+    pub fn evaluate(&self, value: f32) -> f32 {
+        match self {
+            ParameterCurve::Linear { input, output } => {
+                let range = input.y - input.x;
+                let t = if range == 0.0 {
+                    0.0
+                } else {
+                    ((value - input.x) / range).clamp(0.0, 1.0)
+                };
+
+                output.x + (output.y - output.x) * t
+            }
+            ParameterCurve::Points(points) => {
+                let (Some(first), Some(last)) = (points.first(), points.last()) else {
+                    return value;
+                };
+
+                if value <= first.x {
+                    return first.y;
+                }
+
+                points
+                    .windows(2)
+                    .find(|segment| value <= segment[1].x)
+                    .map_or(last.y, |segment| {
+                        ParameterCurve::Linear {
+                            input: Vec2::new(segment[0].x, segment[1].x),
+                            output: Vec2::new(segment[0].y, segment[1].y),
+                        }
+                        .evaluate(value)
+                    })
+            }
+        }
+    }
+}
+
+/// Changes smaller than this are not sent to FMOD.
+const MIN_PARAMETER_CHANGE: f32 = 1e-4;
+
+/// A parameter FMOD rejected is tried again after this many frames.
+const RETRY_FRAMES: u32 = 30;
+
+/// Moves `previous` towards `target` over a frame of `delta` seconds, see
+/// `ParameterMapping::smoothing`. Lands on `target` once it is close enough.
+fn smooth(previous: Option<f32>, target: f32, smoothing: f32, delta: f32) -> f32 {
+    let value = match previous {
+        Some(previous) if smoothing > 0.0 => {
+            let blend = 1.0 - (-delta / smoothing).exp();
+            previous + (target - previous) * blend
+        }
+        _ => target,
+    };
+
+    if (target - value).abs() < MIN_PARAMETER_CHANGE {
+        target
+    } else {
+        value
+    }
+}
+
+/// Whether `value` differs enough from the value FMOD already has.
+/// Once the target is reached it is sent exactly.
+fn needs_update(sent: Option<f32>, value: f32, target: f32) -> bool {
+    match sent {
+        None => true,
+        Some(sent) if value == target => sent != target,
+        Some(sent) => (sent - value).abs() >= MIN_PARAMETER_CHANGE,
+    }
+}
+
+/// Sends the mapped simulation outputs to FMOD. Runs after the outputs have been updated.
+/// Parameters are only set when their value changes.
+pub(crate) fn apply_parameter_bindings(
+    time: Res<Time>,
+    studio: Res<FmodStudio>,
+    mut binding_query: Query<(
+        Entity,
+        &mut PhononParameterBinding,
+        &PhononSourceOutputs,
+        Option<&AudioSource>,
+    )>,
+    mut errors: EventWriter<PhononError>,
+) {
+    let delta = time.delta_seconds();
+
+    for (ent, mut binding, outputs, audio_source) in &mut binding_query {
+        for mapping in binding.0.iter_mut() {
+            let Some(output) = mapping.output.read(outputs) else {
+                if !mapping.reported {
+                    mapping.reported = true;
+                    errors.send(PhononError::InvalidParameterBand {
+                        entity: ent,
+                        parameter: mapping.target.name().to_owned(),
+                        output: mapping.output,
+                    });
+                }
+                continue;
+            };
+
+            let target_value = mapping.curve.evaluate(output);
+            let value = smooth(mapping.value, target_value, mapping.smoothing, delta);
+            mapping.value = Some(value);
+
+            if mapping.frames_until_retry > 0 {
+                mapping.frames_until_retry -= 1;
+                continue;
+            }
+            if !needs_update(mapping.sent, value, target_value) {
+                continue;
+            }
+
+            let result = match &mapping.target {
+                ParameterTarget::Event(name) => match audio_source {
+                    Some(audio_source) => audio_source
+                        .event_instance
+                        .set_parameter_by_name(name, value, false),
+                    None => continue,
+                },
+                ParameterTarget::Global(name) => studio.0.set_parameter_by_name(name, value, false),
+            };
+
+            match result {
+                Ok(_) => {
+                    mapping.sent = Some(value);
+                    mapping.reported = false;
+                }
+                // The parameter might not exist, or the event is not ready yet.
+                // Trying every frame would be wasteful and flood the events.
+                Err(error) => {
+                    mapping.frames_until_retry = RETRY_FRAMES;
+                    if !mapping.reported {
+                        mapping.reported = true;
+                        errors.send(PhononError::ParameterBinding {
+                            entity: ent,
+                            parameter: mapping.target.name().to_owned(),
+                            error,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn linear_curves_clamp() {
+        let curve = ParameterCurve::Linear {
+            input: Vec2::new(0.0, 1.0),
+            output: Vec2::new(10.0, 20.0),
+        };
+        assert_close(curve.evaluate(0.5), 15.0);
+        assert_close(curve.evaluate(-1.0), 10.0);
+        assert_close(curve.evaluate(2.0), 20.0);
+
+        let inverted = ParameterCurve::Linear {
+            input: Vec2::new(0.0, 1.0),
+            output: Vec2::new(1.0, 0.0),
+        };
+        assert_close(inverted.evaluate(0.25), 0.75);
+
+        let empty_range = ParameterCurve::Linear {
+            input: Vec2::new(0.5, 0.5),
+            output: Vec2::new(3.0, 4.0),
+        };
+        assert_close(empty_range.evaluate(0.7), 3.0);
+    }
+
+    #[test]
+    fn default_curve_passes_values_through() {
+        let curve = ParameterCurve::default();
+        for value in [0.0, 0.3, 1.0] {
+            assert_close(curve.evaluate(value), value);
+        }
+    }
+
+    #[test]
+    fn point_curves_interpolate_between_points() {
+        let curve = ParameterCurve::Points(vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.5, 1.0),
+            Vec2::new(1.0, 0.0),
+        ]);
+        assert_close(curve.evaluate(-1.0), 0.0);
+        assert_close(curve.evaluate(0.25), 0.5);
+        assert_close(curve.evaluate(0.5), 1.0);
+        assert_close(curve.evaluate(0.75), 0.5);
+        assert_close(curve.evaluate(2.0), 0.0);
+
+        assert_close(ParameterCurve::Points(Vec::new()).evaluate(0.4), 0.4);
+        assert_close(
+            ParameterCurve::Points(vec![Vec2::new(1.0, 7.0)]).evaluate(2.0),
+            7.0,
+        );
+    }
+
+    #[test]
+    fn smoothing_approaches_the_target() {
+        // The first value and values without smoothing are taken as they are.
+        assert_close(smooth(None, 1.0, 0.5, 0.016), 1.0);
+        assert_close(smooth(Some(0.0), 1.0, 0.0, 0.016), 1.0);
+
+        // About two thirds of the way after `smoothing` seconds.
+        assert_close(smooth(Some(0.0), 1.0, 0.5, 0.5), 1.0 - (-1.0f32).exp());
+
+        let mut value = Some(0.0);
+        for _ in 0..60 {
+            let next = smooth(value, 1.0, 0.5, 1.0 / 60.0);
+            assert!(next > value.unwrap() && next <= 1.0);
+            value = Some(next);
+        }
+
+        // Lands on the target instead of creeping towards it forever.
+        assert_eq!(smooth(Some(1.0 - 1e-5), 1.0, 0.5, 1.0 / 60.0), 1.0);
+    }
+
+    #[test]
+    fn small_changes_are_not_sent() {
+        assert!(needs_update(None, 0.5, 0.5));
+        assert!(!needs_update(Some(0.5), 0.5 + 1e-6, 0.6));
+        assert!(needs_update(Some(0.5), 0.51, 0.6));
+        // The target itself is always sent exactly.
+        assert!(needs_update(Some(0.99999), 1.0, 1.0));
+        assert!(!needs_update(Some(1.0), 1.0, 1.0));
+    }
+
+    #[test]
+    fn invalid_bands_are_not_read() {
+        let outputs = PhononSourceOutputs::default();
+        assert!(SimulationOutput::Transmission { band: 2 }
+            .read(&outputs)
+            .is_some());
+        assert!(SimulationOutput::Transmission { band: 3 }
+            .read(&outputs)
+            .is_none());
+        assert!(SimulationOutput::AirAbsorption { band: 7 }
+            .read(&outputs)
+            .is_none());
+    }
+}
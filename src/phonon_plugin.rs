@@ -1,185 +1,711 @@
+use crate::error::{PhononError, PhononStatus};
+use crate::phonon_listener;
 use crate::phonon_mesh;
 use crate::phonon_mesh::instancing::StaticMeshes;
+#[cfg(feature = "material_files")]
+use crate::phonon_mesh::material::PhononMaterialLoader;
+use crate::phonon_mesh::material::{AudioMaterial, PhononMaterial};
+#[cfg(feature = "fmod")]
+use crate::phonon_parameters;
+use crate::phonon_queries::QuerySimulator;
+use crate::phonon_source;
+use crate::settings::{
+    AudioFormat, PhononBackend, PhononSettings, ReflectionSettings, SimulationExecution,
+};
+use crate::simulation::{
+    SceneLock, SimulationInputs, SimulationOutputs, SimulationResults, SimulationState,
+    SimulatorFactory,
+};
+use crate::simulation_mode::{PhononSimulationMode, StageMode, StageTimers};
+use crate::simulation_thread;
+use crate::simulation_thread::SimulationThread;
+#[cfg(feature = "fmod")]
+use crate::spatializer;
+use bevy::ecs::schedule::{InternedScheduleLabel, ScheduleLabel};
 use bevy::prelude::*;
-use bevy_fmod::prelude::AudioListener;
-use bevy_fmod::prelude::AudioSource;
+use bevy::transform::TransformSystem;
+#[cfg(feature = "fmod")]
+use bevy_fmod::prelude::FmodStudio;
+#[cfg(feature = "fmod")]
 use libfmod::{Dsp, EventInstance};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
 use steamaudio::context::Context;
+#[cfg(feature = "fmod")]
 use steamaudio::fmod;
-use steamaudio::geometry::Orientation;
 use steamaudio::hrtf::Hrtf;
-use steamaudio::simulation::{AirAbsorptionModel, DistanceAttenuationModel, Simulator, Source};
+use steamaudio::simulation::Simulator;
 
-#[derive(Component)]
-struct PhononSource {
-    address: i32,
-    source: Source,
-}
-
-#[derive(Component)]
+#[derive(Component, Reflect, Debug, Default, Serialize, Deserialize)]
+#[reflect(Component, Default)]
 pub struct PhononStaticMeshMarker;
 
-//todo move or remove pub
+/// The systems of `PhononPlugin`, in the order they run. Order your own systems relative to
+/// these, e.g. to change a `PhononSourceSettings` before it is applied in the same frame.
+/// By default they run in `PostUpdate` after transform propagation, see `PhononPlugin::schedule`.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PhononSet {
+    /// Creates, updates and removes Steam Audio sources and geometry.
+    Register,
+    /// Copies the `GlobalTransform`s of listener, sources and geometry and commits the scene.
+    SyncTransforms,
+    /// Commits the inputs and, depending on `SimulationExecution`, runs the simulation.
+    /// Skipped while there is no listener.
+    Simulate,
+}
+
 #[derive(Resource)]
 pub struct SteamSimulation {
     pub context: Context,
     pub hrtf: Hrtf,
-    pub simulator: Simulator,
-    pub scene: steamaudio::scene::Scene,
+    pub(crate) simulator: Simulator,
+    pub(crate) scene: steamaudio::scene::Scene,
+    pub format: AudioFormat,
+    /// Limit for the volumetric occlusion samples of the sources.
+    pub(crate) max_occlusion_samples: u32,
+    /// Set when geometry was added, removed or moved, so the scene is committed once this frame.
+    pub(crate) scene_dirty: bool,
+    /// Held while changing the scene, the simulation commits it from its own thread.
+    pub(crate) scene_lock: Arc<SceneLock>,
+    pub(crate) simulator_factory: SimulatorFactory,
 }
 
-pub struct PhononPlugin;
-
-impl Plugin for PhononPlugin {
-    fn build(&self, app: &mut App) {
-        let sampling_rate = 48000; // Needs to be equal to FMOD sampling rate.
-        let frame_size = 1024;
-        let context = Context::new().unwrap();
+impl SteamSimulation {
+    /// Creates the Steam Audio context, HRTF, root scene and simulator for the given format.
+    /// `format` should be the one FMOD is using, see `PhononSettings::resolve_format`.
+    pub fn new(settings: &PhononSettings, format: AudioFormat) -> Result<Self, PhononError> {
+        let context =
+            Context::new().map_err(|error| PhononError::ContextCreation(format!("{error:?}")))?;
 
-        let hrtf = context.create_hrtf(sampling_rate, frame_size).unwrap();
+        let hrtf = context
+            .create_hrtf(format.sampling_rate, format.frame_size)
+            .map_err(|error| PhononError::HrtfCreation(format!("{error:?}")))?;
 
         // This is the main scene to which all the geometry will be added later
-        let scene = context.create_scene().unwrap();
+        let scene = context
+            .create_scene()
+            .map_err(|error| PhononError::SceneCreation(format!("{error:?}")))?;
         scene.commit();
 
-        // todo! simulationsettings are pretty much hardcoded right now
-        // simulation_settings.max_num_occlusion_samples = 8; // This only sets the max, the actual amount is set per source
-        let mut simulator = context.create_simulator(sampling_rate, frame_size).unwrap();
-        simulator.set_scene(&scene);
-        simulator.set_reflections(4096, 16, 2.0, 1, 1.0);
+        let simulator_factory = SimulatorFactory {
+            context: context.clone(),
+            scene: scene.clone(),
+            format,
+            reflections: settings.reflections.clone(),
+        };
+        let simulator = simulator_factory.create()?;
 
-        fmod::init_fmod(&context);
-        fmod::set_hrtf(&hrtf);
-
-        let settings = fmod::fmod_create_settings(sampling_rate, frame_size);
-        fmod::set_simulation_settings(settings);
-
-        app.insert_resource(SteamSimulation {
-            simulator,
+        Ok(Self {
             context,
             hrtf,
+            simulator,
             scene,
+            format,
+            max_occlusion_samples: settings.max_occlusion_samples,
+            scene_dirty: false,
+            scene_lock: Arc::default(),
+            simulator_factory,
         })
-        .insert_resource(StaticMeshes::default())
-        .add_systems(
-            Update,
-            (
+    }
+}
+
+/// Must be added after `FmodPlugin`, the audio format is read from the FMOD system.
+/// With `PhononBackend::Headless` FMOD is not needed at all, without the `fmod` feature
+/// that is the only backend.
+/// `AssetPlugin` is always required, as well as `Assets<Mesh>` for audio geometry.
+/// Both are part of `DefaultPlugins`.
+/// If Steam Audio cannot be started the error is stored in `PhononStatus` instead of panicking.
+pub struct PhononPlugin {
+    pub settings: PhononSettings,
+    /// Where the `PhononSet`s run. In `PostUpdate` they see the transforms of this frame,
+    /// in other schedules those of the previous frame. There, static geometry that is spawned
+    /// without its final `GlobalTransform` would be baked at the origin.
+    pub schedule: InternedScheduleLabel,
+}
+
+impl Default for PhononPlugin {
+    fn default() -> Self {
+        Self {
+            settings: PhononSettings::default(),
+            schedule: PostUpdate.intern(),
+        }
+    }
+}
+
+impl Plugin for PhononPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PhononError>();
+        register_types(app);
+
+        // `init_asset` panics without it, `init_steam_audio` reports it.
+        if app.is_plugin_added::<AssetPlugin>() {
+            app.init_asset::<PhononMaterial>();
+            #[cfg(feature = "material_files")]
+            app.init_asset_loader::<PhononMaterialLoader>();
+        }
+
+        // Also without Steam Audio, so systems of the app that use them keep working.
+        app.insert_resource(self.settings.source_policy.clone())
+            .insert_resource(self.settings.simulation_mode.clone())
+            .insert_resource(phonon_listener::PhononListener::default());
+        #[cfg(feature = "gltf")]
+        app.init_resource::<phonon_mesh::material_map::PhononMaterialMap>();
+
+        let steam_simulation = match self.init_steam_audio(app) {
+            Ok(steam_simulation) => steam_simulation,
+            Err(error) => {
+                error!("Steam Audio is unavailable: {error}");
+                app.insert_resource(PhononStatus::Unavailable(error));
+                return;
+            }
+        };
+
+        let settings = &self.settings;
+        let schedule = self.schedule;
+
+        let simulation_state = SimulationState::new(&steam_simulation);
+        let simulation_results = SimulationResults::default();
+
+        match &settings.execution {
+            SimulationExecution::MainThread => {
+                app.insert_resource(simulation_state)
+                    .add_systems(
+                        schedule,
+                        commit_audio_scene
+                            .in_set(PhononSet::SyncTransforms)
+                            .after(phonon_mesh::update_audio_mesh_transforms),
+                    )
+                    .add_systems(
+                        schedule,
+                        (
+                            update_steam_audio,
+                            phonon_source::update_phonon_source_outputs,
+                            phonon_listener::update_phonon_listener_outputs,
+                        )
+                            .chain()
+                            .in_set(PhononSet::Simulate),
+                    );
+            }
+            SimulationExecution::Background => {
+                let simulation_thread = match SimulationThread::spawn(
+                    simulation_state,
+                    simulation_results.clone(),
+                    settings.simulation_mode.clone(),
+                ) {
+                    Ok(simulation_thread) => simulation_thread,
+                    Err(error) => {
+                        error!("Steam Audio is unavailable: {error}");
+                        app.insert_resource(PhononStatus::Unavailable(error));
+                        return;
+                    }
+                };
+
+                // The outputs are those of the latest simulation the thread finished.
+                app.insert_resource(simulation_thread)
+                    .add_systems(
+                        schedule,
+                        simulation_thread::request_scene_commit
+                            .in_set(PhononSet::SyncTransforms)
+                            .after(phonon_mesh::update_audio_mesh_transforms),
+                    )
+                    .add_systems(
+                        schedule,
+                        (
+                            simulation_thread::commit_steam_audio,
+                            phonon_source::update_phonon_source_outputs,
+                            phonon_listener::update_phonon_listener_outputs,
+                        )
+                            .chain()
+                            .in_set(PhononSet::Simulate),
+                    );
+            }
+        }
+
+        // Without it only the queries are unavailable.
+        match QuerySimulator::new(&steam_simulation, settings) {
+            Ok(query_simulator) => {
+                app.insert_resource(query_simulator);
+            }
+            Err(error) => {
+                error!("PhononQueries are unavailable: {error}");
+                app.world.send_event(error);
+            }
+        }
+
+        app.insert_resource(PhononStatus::Running)
+            .insert_resource(steam_simulation)
+            .insert_resource(simulation_results)
+            .insert_resource(SimulationInputs::default())
+            .insert_resource(StaticMeshes::default())
+            .insert_resource(phonon_mesh::AudioMeshes::default())
+            .insert_resource(phonon_source::PhononSources::default())
+            .configure_sets(
+                schedule,
                 (
-                    register_phonon_sources,
-                    phonon_mesh::register_audio_meshes,
-                    phonon_mesh::update_audio_mesh_transforms,
-                    update_steam_audio_listener,
-                    update_steam_audio_source,
-                ),
-                update_steam_audio,
+                    PhononSet::Register,
+                    PhononSet::SyncTransforms,
+                    PhononSet::Simulate.run_if(phonon_listener::has_phonon_listener),
+                )
+                    .chain()
+                    .after(TransformSystem::TransformPropagate),
             )
-                .chain(),
-        );
+            .add_systems(
+                schedule,
+                (
+                    (
+                        match settings.backend {
+                            #[cfg(feature = "fmod")]
+                            PhononBackend::Fmod => (
+                                phonon_source::remove_phonon_sources,
+                                (
+                                    phonon_source::register_phonon_sources,
+                                    spatializer::params::update_spatializer_params,
+                                ),
+                            )
+                                .chain(),
+                            PhononBackend::Headless => (
+                                phonon_source::remove_headless_sources,
+                                phonon_source::register_headless_sources,
+                            )
+                                .chain(),
+                        },
+                        phonon_source::update_phonon_source_settings,
+                    )
+                        .chain(),
+                    (
+                        phonon_mesh::remove_audio_meshes,
+                        phonon_mesh::handle_mesh_asset_events,
+                        phonon_mesh::handle_material_asset_events,
+                        phonon_mesh::handle_palette_changes,
+                        phonon_mesh::register_audio_meshes,
+                        phonon_mesh::update_audio_geometry_enabled,
+                    )
+                        .chain(),
+                )
+                    .in_set(PhononSet::Register),
+            )
+            .add_systems(
+                schedule,
+                (
+                    phonon_mesh::update_audio_mesh_transforms,
+                    phonon_listener::update_steam_audio_listener,
+                    phonon_source::update_steam_audio_source,
+                )
+                    .in_set(PhononSet::SyncTransforms),
+            );
+
+        #[cfg(feature = "fmod")]
+        if settings.backend == PhononBackend::Fmod {
+            app.add_systems(
+                schedule,
+                phonon_parameters::apply_parameter_bindings
+                    .in_set(PhononSet::Simulate)
+                    .after(phonon_source::update_phonon_source_outputs),
+            );
+        }
+
+        // glTF files are optional, the material map also works for other meshes.
+        #[cfg(feature = "gltf")]
+        app.init_resource::<phonon_mesh::material_map::GltfMaterialNames>()
+            .add_systems(
+                schedule,
+                (
+                    phonon_mesh::material_map::update_gltf_material_names
+                        .run_if(resource_exists::<Assets<bevy::gltf::Gltf>>),
+                    phonon_mesh::material_map::attach_auto_audio_meshes,
+                )
+                    .chain()
+                    .in_set(PhononSet::Register)
+                    .before(phonon_mesh::register_audio_meshes),
+            );
     }
 }
 
-fn update_steam_audio_listener(
-    mut sim_res: ResMut<SteamSimulation>,
-    listener_query: Query<&GlobalTransform, With<AudioListener>>,
-) {
-    let listener_transform = listener_query.get_single().unwrap();
-    let (_rotation, rotation, translation) = listener_transform.to_scale_rotation_translation();
+impl PhononPlugin {
+    /// Runs the `PhononSet`s in another schedule, e.g. `FixedPostUpdate`.
+    pub fn with_schedule(mut self, schedule: impl ScheduleLabel) -> Self {
+        self.schedule = schedule.intern();
+        self
+    }
+
+    fn init_steam_audio(&self, app: &App) -> Result<SteamSimulation, PhononError> {
+        if !app.is_plugin_added::<AssetPlugin>() {
+            return Err(PhononError::MissingAssetPlugin);
+        }
 
-    sim_res.simulator.set_listener(Orientation {
-        translation,
-        rotation,
-    });
+        let settings = &self.settings;
+        settings.validate()?;
+
+        match settings.backend {
+            #[cfg(feature = "fmod")]
+            PhononBackend::Fmod => init_fmod_plugin(app, settings),
+            PhononBackend::Headless => SteamSimulation::new(settings, settings.headless_format()),
+        }
+    }
 }
 
-fn update_steam_audio_source(mut source_query: Query<(&GlobalTransform, &mut PhononSource)>) {
-    for (source_transform, mut phonon_source) in source_query.iter_mut() {
-        let (_rotation, rotation, translation) = source_transform.to_scale_rotation_translation();
+/// Creates the simulation in the format of the FMOD mixer and hands it to the Steam Audio
+/// FMOD plugin.
+#[cfg(feature = "fmod")]
+fn init_fmod_plugin(app: &App, settings: &PhononSettings) -> Result<SteamSimulation, PhononError> {
+    let studio = app
+        .world
+        .get_resource::<FmodStudio>()
+        .ok_or(PhononError::MissingFmodPlugin)?;
 
-        phonon_source.source.set_source(Orientation {
-            translation,
-            rotation,
-        });
+    let format = settings.resolve_format(fmod_audio_format(studio)?)?;
+
+    let steam_simulation = SteamSimulation::new(settings, format)?;
+
+    // The Steam Audio FMOD plugin does not report whether it accepted these.
+    fmod::init_fmod(&steam_simulation.context);
+    fmod::set_hrtf(&steam_simulation.hrtf);
+
+    let fmod_settings = fmod::fmod_create_settings(format.sampling_rate, format.frame_size);
+    fmod::set_simulation_settings(fmod_settings);
+
+    Ok(steam_simulation)
+}
+
+/// Reads the sampling rate and DSP buffer length the FMOD mixer is using.
+#[cfg(feature = "fmod")]
+fn fmod_audio_format(studio: &FmodStudio) -> Result<AudioFormat, PhononError> {
+    let core_system = studio
+        .0
+        .get_core_system()
+        .map_err(PhononError::FmodSystem)?;
+    let (sampling_rate, _speaker_mode, _num_raw_speakers) = core_system
+        .get_software_format()
+        .map_err(PhononError::FmodSystem)?;
+    let (frame_size, _num_buffers) = core_system
+        .get_dsp_buffer_size()
+        .map_err(PhononError::FmodSystem)?;
+
+    Ok(AudioFormat {
+        sampling_rate: sampling_rate as u32,
+        frame_size,
+    })
+}
+
+/// Commits the root scene at most once per frame, and only if the geometry changed.
+fn commit_audio_scene(mut sim_res: ResMut<SteamSimulation>, state: Res<SimulationState>) {
+    if std::mem::take(&mut sim_res.scene_dirty) {
+        state.commit_scene();
     }
 }
 
-fn update_steam_audio(sim_res: ResMut<SteamSimulation>) {
-    // Commit changes to the sources, listener and scene.
-    sim_res.simulator.commit();
+fn update_steam_audio(
+    mut state: ResMut<SimulationState>,
+    mut inputs: ResMut<SimulationInputs>,
+    results: Res<SimulationResults>,
+    mode: Res<PhononSimulationMode>,
+    mut timers: Local<StageTimers>,
+    mut outputs: Local<SimulationOutputs>,
+) {
+    // Commit changes to the sources and listener.
+    state.commit(std::mem::take(&mut *inputs));
 
-    sim_res.simulator.run_direct();
-    sim_res.simulator.run_reflections(); //todo make optional
+    let now = Instant::now();
+
+    if timers.direct.tick(&mode.direct, now) {
+        state.run_direct();
+    }
+    if timers.reflections.tick(&mode.reflections, now) {
+        state.run_reflections();
+    }
+    if timers.pathing.tick(&mode.pathing, now) {
+        state.run_pathing();
+    }
 
     // The Steam Audio FMOD plugin will periodically collect the simulation outputs
     // as long as the plugin has handles to the Steam Audio sources.
-    // See function `register_phonon_sources`.
+    // See function `phonon_source::register_phonon_sources`.
+    state.read_outputs(&mut outputs);
+    results.publish(&mut outputs);
 }
 
-/// Currently all bevy_fmod audio sources will be converted to Steam Audio sources.
-fn register_phonon_sources(
-    mut audio_sources: Query<(Entity, &AudioSource), Without<PhononSource>>,
-    mut commands: Commands,
-    sim_res: Res<SteamSimulation>,
-) {
-    for (audio_entity, audio_source_fmod) in audio_sources.iter_mut() {
-        if let Some(phonon_dsp) = get_phonon_spatializer(audio_source_fmod.event_instance) {
-            let mut source = sim_res.simulator.create_source(true).unwrap();
-            source.set_distance_attenuation(DistanceAttenuationModel::Default);
-            source.set_air_absorption(AirAbsorptionModel::Default);
-            source.set_occlusion();
-            source.set_transmission(1);
-            source.set_reflections();
-            source.set_active(true);
-
-            let source_address = fmod::add_source(&source);
-            let simulation_outputs_parameter_index = 33; //todo explain where this number comes from
-
-            // By setting this field the Steam Audio FMOD plugin can retrieve the
-            // simulation results like occlusion and reflection.
-            phonon_dsp
-                .set_parameter_int(simulation_outputs_parameter_index, source_address)
-                .unwrap();
-
-            commands.entity(audio_entity).insert(PhononSource {
-                address: source_address,
-                source,
-            });
-        }
-    }
+/// A `PhononPlugin` with `PhononBackend::Headless` and just the plugins it needs.
+#[cfg(test)]
+pub(crate) fn headless_app(settings: PhononSettings) -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, AssetPlugin::default(), TransformPlugin))
+        .init_asset::<Mesh>()
+        .add_plugins(PhononPlugin {
+            settings: PhononSettings {
+                backend: PhononBackend::Headless,
+                ..settings
+            },
+            ..default()
+        });
+    app
 }
 
-// Deregister phonon source
-// impl Drop for PhononSource {
-//     fn drop(&mut self) {
-//         println!("Dropping source!");
-//         fmod::remove_source(self.address);
-//     }
-// }
-
-/// The goal here is to find the Steam Audio Spatializer DSP associated with an instance.
-/// This way we can later set its parameters.
-/// The DSP can basically be anywhere in the DSP chain, so we have to search for it.
-/// (That's the idea, but see issue #3. Once that's fixed it should also be noted that
-/// the DSP can also be on the master track itself, which is channel_group).
+/// Returns the first Steam Audio Spatializer of the event instance.
+#[cfg(feature = "fmod")]
+#[deprecated(
+    note = "events can contain several spatializers, use `spatializer::get_phonon_spatializers`"
+)]
 pub fn get_phonon_spatializer(instance: EventInstance) -> Option<Dsp> {
-    if let Ok(channel_group) = instance.get_channel_group() {
-        // 0 is the DSP all the way on the right on the master track
-        return Some(channel_group.get_dsp(0).unwrap());
-
-        // for index_group in 0..num_groups {
-        //     let group = channel_group.get_group(index_group).unwrap();
-        //     let group_num_dsp = group.get_num_ds_ps().unwrap();
-        //
-        //     for index_dsp in 0..group_num_dsp {
-        //         let dsp = group.get_dsp(index_dsp).unwrap();
-        //         let dsp_info = dsp.get_info().unwrap(); // this line seems to cause issues when Steam Audio is configured for reflection simulations??
-        //
-        //         if dsp_info.0 == "Steam Audio Spatializer" {
-        //             println!("index group {} index dsp {}", index_group, index_dsp);
-        //             return Some(dsp);
-        //         }
-        //     }
-        // }
+    spatializer::get_phonon_spatializers(instance)
+        .ok()?
+        .into_iter()
+        .next()
+}
+
+/// Makes the public types available to scenes and inspectors, also when Steam Audio is
+/// unavailable so scenes still load.
+fn register_types(app: &mut App) {
+    app.register_type::<PhononStaticMeshMarker>()
+        .register_type::<PhononMaterial>()
+        .register_type::<AudioMaterial>()
+        .register_type::<phonon_mesh::NeedsAudioMesh>()
+        .register_type::<phonon_mesh::PhononMaterialPalette>()
+        .register_type::<phonon_mesh::PhononMeshMobility>()
+        .register_type::<phonon_mesh::AudioGeometryEnabled>()
+        .register_type::<phonon_mesh::AudioMeshFailed>()
+        .register_type::<phonon_source::PhononSourceSettings>()
+        .register_type::<phonon_source::PhononSourceOutputs>()
+        .register_type::<phonon_source::OcclusionModel>()
+        .register_type::<phonon_source::PhononSourcePolicy>()
+        .register_type::<phonon_listener::PhononActiveListener>()
+        .register_type::<phonon_listener::PhononListener>()
+        .register_type::<phonon_listener::PhononListenerOutputs>()
+        .register_type::<PhononSettings>()
+        .register_type::<ReflectionSettings>()
+        .register_type::<SimulationExecution>()
+        .register_type::<PhononBackend>()
+        .register_type::<AudioFormat>()
+        .register_type::<PhononSimulationMode>()
+        .register_type::<StageMode>();
+
+    #[cfg(feature = "fmod")]
+    app.register_type::<phonon_parameters::PhononParameterBinding>()
+        .register_type::<phonon_parameters::ParameterMapping>()
+        .register_type::<phonon_parameters::SimulationOutput>()
+        .register_type::<phonon_parameters::ParameterTarget>()
+        .register_type::<phonon_parameters::ParameterCurve>()
+        .register_type::<spatializer::params::SpatializerParams>()
+        .register_type::<spatializer::params::ApplyType>()
+        .register_type::<spatializer::params::HrtfInterpolation>();
+
+    #[cfg(feature = "gltf")]
+    app.register_type::<phonon_mesh::material_map::PhononMaterialMap>()
+        .register_type::<phonon_mesh::material_map::AutoAudioMesh>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phonon_listener::{PhononActiveListener, PhononListenerOutputs};
+    use crate::phonon_mesh::material::materials;
+    use crate::phonon_mesh::{
+        AudioGeometryEnabled, AudioMeshes, NeedsAudioMesh, PhononMaterialPalette,
+        PhononMeshMobility,
+    };
+    use crate::phonon_queries::PhononQueries;
+    use crate::phonon_source::{PhononSourceOutputs, PhononSourceSettings};
+    use bevy::ecs::entity::EntityHashMap;
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::scene::serde::SceneDeserializer;
+    use serde::de::DeserializeSeed;
+
+    /// Number of times the root scene has been committed.
+    fn scene_commits(app: &App) -> u64 {
+        *app.world.resource::<SteamSimulation>().scene_lock.lock()
     }
 
-    None
+    #[test]
+    fn scene_is_committed_once_per_frame_with_changes() {
+        let mut app = headless_app(PhononSettings::default());
+        let wall = app
+            .world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Cuboid::new(10.0, 10.0, 0.2));
+        let walls = (0..2)
+            .map(|index| {
+                app.world
+                    .spawn((
+                        wall.clone(),
+                        NeedsAudioMesh::new(materials::CONCRETE),
+                        TransformBundle::from_transform(Transform::from_xyz(
+                            index as f32,
+                            0.0,
+                            0.0,
+                        )),
+                    ))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+
+        let before = scene_commits(&app);
+        app.update();
+        let registered = scene_commits(&app);
+        assert_eq!(registered, before + 1);
+
+        for _ in 0..3 {
+            app.update();
+        }
+        assert_eq!(scene_commits(&app), registered);
+
+        for _ in 0..3 {
+            for wall in &walls {
+                app.world.get_mut::<Transform>(*wall).unwrap().translation.y += 1.0;
+            }
+            app.update();
+        }
+        assert_eq!(scene_commits(&app), registered + 3);
+    }
+
+    #[test]
+    fn resources_exist_without_steam_audio() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, PhononPlugin::default()));
+        app.update();
+
+        assert!(matches!(
+            app.world.resource::<PhononStatus>(),
+            PhononStatus::Unavailable(_)
+        ));
+        assert!(app.world.contains_resource::<PhononSimulationMode>());
+        assert!(app
+            .world
+            .contains_resource::<phonon_source::PhononSourcePolicy>());
+        assert!(app
+            .world
+            .contains_resource::<phonon_listener::PhononListener>());
+        #[cfg(feature = "gltf")]
+        assert!(app
+            .world
+            .contains_resource::<phonon_mesh::material_map::PhononMaterialMap>());
+    }
+
+    #[test]
+    fn components_survive_a_scene_round_trip() {
+        let mut app = headless_app(PhononSettings::default());
+        let mesh = app
+            .world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Cuboid::new(10.0, 10.0, 0.2));
+        let wall = app
+            .world
+            .spawn((
+                mesh,
+                NeedsAudioMesh::new(materials::GLASS),
+                PhononMaterialPalette(vec![materials::WOOD, materials::METAL]),
+                PhononMeshMobility::Static,
+                AudioGeometryEnabled(true),
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, -2.5)),
+            ))
+            .id();
+        let source_settings = PhononSourceSettings {
+            reflections: false,
+            ..default()
+        };
+        let source = app
+            .world
+            .spawn((
+                source_settings.clone(),
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, -5.0)),
+            ))
+            .id();
+        let listener = app
+            .world
+            .spawn((PhononActiveListener, TransformBundle::default()))
+            .id();
+        app.update();
+        app.update();
+        let outputs = app
+            .world
+            .get::<PhononSourceOutputs>(source)
+            .unwrap()
+            .clone();
+
+        // Meshes are not part of the scene and outputs are simulated again.
+        let scene = DynamicSceneBuilder::from_world(&app.world)
+            .deny::<Handle<Mesh>>()
+            .deny::<PhononSourceOutputs>()
+            .extract_entities([wall, source, listener].into_iter())
+            .build();
+        let serialized = scene
+            .serialize_ron(app.world.resource::<AppTypeRegistry>())
+            .unwrap();
+
+        let mut reloaded = headless_app(PhononSettings::default());
+        let deserialized = {
+            let type_registry = reloaded.world.resource::<AppTypeRegistry>().read();
+            let mut deserializer = ron::de::Deserializer::from_str(&serialized).unwrap();
+            SceneDeserializer {
+                type_registry: &type_registry,
+            }
+            .deserialize(&mut deserializer)
+            .unwrap()
+        };
+        let mut entity_map = EntityHashMap::default();
+        deserialized
+            .write_to_world(&mut reloaded.world, &mut entity_map)
+            .unwrap();
+
+        let reloaded_wall = reloaded.world.entity(entity_map[&wall]);
+        assert_eq!(
+            reloaded_wall.get::<NeedsAudioMesh>().unwrap().0,
+            AudioMaterial::Inline(materials::GLASS)
+        );
+        assert_eq!(
+            reloaded_wall.get::<PhononMaterialPalette>().unwrap().0,
+            vec![materials::WOOD, materials::METAL]
+        );
+        assert_eq!(
+            reloaded_wall.get::<PhononMeshMobility>(),
+            Some(&PhononMeshMobility::Static)
+        );
+        assert_eq!(
+            reloaded_wall.get::<AudioGeometryEnabled>(),
+            Some(&AudioGeometryEnabled(true))
+        );
+        assert_eq!(
+            reloaded
+                .world
+                .get::<PhononSourceSettings>(entity_map[&source]),
+            Some(&source_settings)
+        );
+        assert!(reloaded
+            .world
+            .entity(entity_map[&listener])
+            .contains::<PhononActiveListener>());
+        // Its keys would be the entities of the old world.
+        assert!(!reloaded
+            .world
+            .entity(entity_map[&listener])
+            .contains::<PhononListenerOutputs>());
+
+        // The reloaded entities are picked up like freshly spawned ones.
+        let mesh = reloaded
+            .world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Cuboid::new(10.0, 10.0, 0.2));
+        reloaded.world.entity_mut(entity_map[&wall]).insert(mesh);
+        reloaded.update();
+        reloaded.update();
+
+        assert!(reloaded
+            .world
+            .resource::<AudioMeshes>()
+            .contains_key(&entity_map[&wall]));
+        let occlusion = reloaded
+            .world
+            .run_system_once(|mut queries: PhononQueries| {
+                queries
+                    .query(Vec3::ZERO, Vec3::new(0.0, 0.0, -5.0), 0.0)
+                    .unwrap()
+                    .occlusion
+            });
+        assert_eq!(occlusion, 0.0);
+        assert_eq!(
+            reloaded
+                .world
+                .get::<PhononSourceOutputs>(entity_map[&source]),
+            Some(&outputs)
+        );
+    }
 }
@@ -0,0 +1,202 @@
+use crate::error::PhononError;
+use crate::phonon_plugin::SteamSimulation;
+use crate::settings::PhononSettings;
+use crate::simulation::SceneLock;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use steamaudio::geometry::Orientation;
+use steamaudio::simulation::{AirAbsorptionModel, DistanceAttenuationModel, Simulator, Source};
+
+/// How sound travels between two points, see `PhononQueries::query`.
+/// Values are between 0 and 1, per band values are for 400 Hz, 2.5 KHz and 15 KHz.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PhononQueryResult {
+    /// 1 is not occluded at all.
+    pub occlusion: f32,
+    /// How much of the occluded sound passes through the geometry.
+    pub transmission: [f32; 3],
+}
+
+impl PhononQueryResult {
+    /// Per band fraction of the sound that arrives, directly or through the geometry.
+    /// Distance attenuation is not included.
+    pub fn gain(&self) -> [f32; 3] {
+        self.transmission
+            .map(|transmission| self.occlusion + (1.0 - self.occlusion) * transmission)
+    }
+}
+
+/// A second simulator on the same scene, so queries do not disturb the listener and
+/// sources of the actual simulation.
+#[derive(Resource)]
+pub(crate) struct QuerySimulator {
+    simulator: Simulator,
+    source: Source,
+    max_occlusion_samples: u32,
+    scene_lock: Arc<SceneLock>,
+    /// Scene commits this simulator has seen, it commits again to pick up new geometry.
+    scene_commits: u64,
+}
+
+impl QuerySimulator {
+    pub(crate) fn new(
+        steam_simulation: &SteamSimulation,
+        settings: &PhononSettings,
+    ) -> Result<Self, PhononError> {
+        let format = steam_simulation.format;
+        let simulator_error = |error| PhononError::QuerySimulatorCreation(format!("{error:?}"));
+
+        let mut simulator = steam_simulation
+            .context
+            .create_simulator(format.sampling_rate, format.frame_size)
+            .map_err(simulator_error)?;
+        simulator.set_scene(&steam_simulation.scene);
+
+        let mut source = simulator.create_source(true).map_err(simulator_error)?;
+        source.set_distance_attenuation(DistanceAttenuationModel::Default);
+        source.set_air_absorption(AirAbsorptionModel::Default);
+        source.disable_reflections();
+        source.disable_pathing();
+        source.set_active(true);
+        simulator.commit();
+
+        Ok(Self {
+            simulator,
+            source,
+            max_occlusion_samples: settings.max_occlusion_samples,
+            scene_lock: steam_simulation.scene_lock.clone(),
+            scene_commits: 0,
+        })
+    }
+}
+
+/// Acoustic questions between two arbitrary points, independent of the listener.
+/// For example whether an NPC can hear the player. Uses the same geometry and materials
+/// as the simulation, as of the last scene commit.
+///
+/// Every query runs a direct simulation on the calling thread, so keep the number of
+/// queries per frame reasonable. All methods return `None` while Steam Audio is unavailable.
+#[derive(SystemParam)]
+pub struct PhononQueries<'w> {
+    query_simulator: Option<ResMut<'w, QuerySimulator>>,
+}
+
+impl<'w> PhononQueries<'w> {
+    /// Occlusion and transmission of the sound of a source at `to` heard at `from`.
+    /// A `source_radius` above 0 treats the source as a sphere, which allows for
+    /// partial occlusion. Otherwise a single ray is used.
+    pub fn query(&mut self, from: Vec3, to: Vec3, source_radius: f32) -> Option<PhononQueryResult> {
+        let query_simulator = self.query_simulator.as_mut()?;
+        let QuerySimulator {
+            simulator,
+            source,
+            max_occlusion_samples,
+            scene_lock,
+            scene_commits,
+        } = query_simulator.as_mut();
+
+        if source_radius > 0.0 {
+            source.set_volumetric_occlusion(source_radius, *max_occlusion_samples);
+        } else {
+            source.set_occlusion();
+        }
+        source.set_transmission(1);
+
+        simulator.set_listener(Orientation {
+            translation: from,
+            rotation: Quat::IDENTITY,
+        });
+        source.set_source(Orientation {
+            translation: to,
+            rotation: Quat::IDENTITY,
+        });
+
+        // The scene must not be committed while the query runs.
+        let commits = scene_lock.lock();
+        if *scene_commits != *commits {
+            simulator.commit();
+            *scene_commits = *commits;
+        }
+        simulator.run_direct();
+        drop(commits);
+
+        let direct = source.get_outputs().direct;
+
+        Some(PhononQueryResult {
+            occlusion: direct.occlusion,
+            transmission: direct.transmission,
+        })
+    }
+
+    /// 1 is not occluded at all, see `query` for `source_radius`.
+    pub fn occlusion(&mut self, from: Vec3, to: Vec3, source_radius: f32) -> Option<f32> {
+        self.query(from, to, source_radius)
+            .map(|result| result.occlusion)
+    }
+
+    /// Per band fraction of the sound that passes through the geometry between the points.
+    /// Only meaningful when they are occluded.
+    pub fn transmission(&mut self, from: Vec3, to: Vec3) -> Option<[f32; 3]> {
+        self.query(from, to, 0.0).map(|result| result.transmission)
+    }
+
+    /// Whether sound travels from `to` to `from` without passing through geometry.
+    /// A single ray is cast, so the occlusion is either 0 or 1 and the threshold of 0.5
+    /// only separates the two. Use `occlusion` with a radius for partial occlusion.
+    pub fn is_line_of_sound(&mut self, from: Vec3, to: Vec3) -> Option<bool> {
+        self.occlusion(from, to, 0.0)
+            .map(|occlusion| occlusion > 0.5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phonon_mesh::material::materials;
+    use crate::phonon_mesh::NeedsAudioMesh;
+    use crate::phonon_plugin::headless_app;
+    use bevy::ecs::system::RunSystemOnce;
+
+    const LISTENER: Vec3 = Vec3::ZERO;
+    const SOURCE: Vec3 = Vec3::new(0.0, 0.0, -5.0);
+
+    fn run_query(app: &mut App) -> (PhononQueryResult, bool) {
+        app.world.run_system_once(|mut queries: PhononQueries| {
+            (
+                queries.query(LISTENER, SOURCE, 0.0).unwrap(),
+                queries.is_line_of_sound(LISTENER, SOURCE).unwrap(),
+            )
+        })
+    }
+
+    #[test]
+    fn wall_blocks_the_line_of_sound() {
+        let mut app = headless_app(PhononSettings::default());
+        app.update();
+
+        let (open, line_of_sound) = run_query(&mut app);
+        assert_eq!(open.occlusion, 1.0);
+        assert_eq!(open.gain(), [1.0; 3]);
+        assert!(line_of_sound);
+
+        let wall = app
+            .world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Cuboid::new(10.0, 10.0, 0.2));
+        app.world.spawn((
+            wall,
+            NeedsAudioMesh::new(materials::GLASS),
+            TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, -2.5)),
+        ));
+        app.update();
+
+        let (walled, line_of_sound) = run_query(&mut app);
+        assert_eq!(walled.occlusion, 0.0);
+        for transmission in walled.transmission {
+            assert!(transmission > 0.0 && transmission < 1.0);
+        }
+        assert!(!line_of_sound);
+    }
+}
@@ -0,0 +1,738 @@
+use crate::error::PhononError;
+use crate::phonon_plugin::SteamSimulation;
+use crate::simulation;
+use crate::simulation::{SimulationInputs, SimulationResults};
+use crate::simulation_mode::PhononSimulationMode;
+#[cfg(feature = "fmod")]
+use crate::spatializer::params::Spatializer;
+#[cfg(feature = "fmod")]
+use crate::spatializer::{get_phonon_spatializers, SpatializerError};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+#[cfg(feature = "fmod")]
+use bevy_fmod::prelude::AudioSource;
+#[cfg(feature = "fmod")]
+use libfmod::Dsp;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(feature = "fmod")]
+use steamaudio::fmod;
+use steamaudio::simulation::{AirAbsorptionModel, DistanceAttenuationModel, Source};
+
+/// Marks an entity whose bevy_fmod `AudioSource` has been registered with Steam Audio.
+/// The Steam Audio source itself is owned by the `SimulationState`, `PhononSources` keeps
+/// track of it so it can still be deregistered after the entity has been despawned.
+#[derive(Component)]
+pub(crate) struct PhononSource;
+
+pub(crate) struct RegisteredSource {
+    /// `None` for `PhononBackend::Headless`.
+    #[cfg(feature = "fmod")]
+    pub(crate) fmod: Option<FmodSource>,
+}
+
+impl RegisteredSource {
+    /// Stops the FMOD plugin from reading the outputs of this source.
+    fn release(&self) {
+        #[cfg(feature = "fmod")]
+        if let Some(fmod_source) = &self.fmod {
+            fmod_source.release();
+        }
+    }
+}
+
+/// How the FMOD plugin reads the simulation outputs of a source.
+#[cfg(feature = "fmod")]
+pub(crate) struct FmodSource {
+    /// Handle of the source in the FMOD plugin.
+    address: i32,
+    /// The Steam Audio Spatializers that read the simulation outputs of this source.
+    pub(crate) dsps: Vec<Dsp>,
+}
+
+#[cfg(feature = "fmod")]
+impl FmodSource {
+    fn release(&self) {
+        fmod::remove_source(self.address);
+
+        // The DSPs are gone already if the event instance was released, so errors are expected.
+        for dsp in &self.dsps {
+            let _ = Spatializer(*dsp).set_simulation_outputs(-1);
+        }
+    }
+}
+
+/// All Steam Audio sources that are currently being simulated, by entity.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub(crate) struct PhononSources(HashMap<Entity, RegisteredSource>);
+
+/// The event of this entity does not contain a Steam Audio Spatializer or its DSP graph
+/// could not be read, so there is no point in searching for one every frame.
+/// Events can create their DSPs later, e.g. once a track starts playing, so the search is
+/// repeated every few frames and whenever the `AudioSource` changes.
+#[cfg(feature = "fmod")]
+#[derive(Component)]
+pub(crate) struct MissingPhononSpatializer {
+    frames_until_retry: u32,
+}
+
+#[cfg(feature = "fmod")]
+impl MissingPhononSpatializer {
+    const RETRY_FRAMES: u32 = 30;
+
+    fn new() -> Self {
+        Self {
+            frames_until_retry: Self::RETRY_FRAMES,
+        }
+    }
+
+    /// Counts down a frame, returns true once it is time to search again.
+    fn retry(&mut self) -> bool {
+        self.frames_until_retry = self.frames_until_retry.saturating_sub(1);
+        if self.frames_until_retry > 0 {
+            return false;
+        }
+
+        self.frames_until_retry = Self::RETRY_FRAMES;
+        true
+    }
+}
+
+/// The results of the latest finished simulation of a registered source, as applied by the
+/// FMOD plugin. Inserted on every registered source, so gameplay code can tell how well the
+/// listener hears it without casting its own rays.
+/// With `SimulationExecution::Background` they are handed over once a run has finished, so
+/// they never mix two runs but can lag a few frames behind.
+/// Values are between 0 and 1, per band values are for 400 Hz, 2.5 KHz and 15 KHz.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct PhononSourceOutputs {
+    pub distance_attenuation: f32,
+    pub air_absorption: [f32; 3],
+    pub directivity: f32,
+    /// 1 is not occluded at all.
+    pub occlusion: f32,
+    /// How much of the occluded sound passes through the geometry.
+    pub transmission: [f32; 3],
+    /// Reverb time in seconds estimated by the reflection simulation, 0 without reflections.
+    pub reverb_time: [f32; 3],
+}
+
+impl Default for PhononSourceOutputs {
+    /// Nothing attenuated, which is also what the spatializer does before the first simulation.
+    fn default() -> Self {
+        Self {
+            distance_attenuation: 1.0,
+            air_absorption: [1.0; 3],
+            directivity: 1.0,
+            occlusion: 1.0,
+            transmission: [1.0; 3],
+            reverb_time: [0.0; 3],
+        }
+    }
+}
+
+/// Must only be used by the thread running the simulation, in between runs.
+impl From<&Source> for PhononSourceOutputs {
+    fn from(source: &Source) -> Self {
+        let outputs = source.get_outputs();
+        let direct = outputs.direct;
+
+        Self {
+            distance_attenuation: direct.distance_attenuation,
+            air_absorption: direct.air_absorption,
+            directivity: direct.directivity,
+            occlusion: direct.occlusion,
+            transmission: direct.transmission,
+            reverb_time: outputs.reflections.reverb_times,
+        }
+    }
+}
+
+impl PhononSourceOutputs {
+    /// Overall gain of the direct sound, averaged over the bands.
+    pub fn gain(&self) -> f32 {
+        let average = |bands: [f32; 3]| bands.iter().sum::<f32>() / 3.0;
+        let occluded = self.occlusion + (1.0 - self.occlusion) * average(self.transmission);
+
+        self.distance_attenuation * average(self.air_absorption) * self.directivity * occluded
+    }
+}
+
+/// Selects which Steam Audio effects are simulated for a bevy_fmod `AudioSource`.
+/// Depending on the `PhononSourcePolicy` only sources with this component are registered.
+/// Changing it at runtime reconfigures the Steam Audio source.
+#[derive(Component, Reflect, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct PhononSourceSettings {
+    /// `None` disables occlusion and therefore also transmission.
+    pub occlusion: Option<OcclusionModel>,
+    /// Number of rays used for transmission, 0 disables it. Requires occlusion.
+    pub transmission_rays: u32,
+    pub reflections: bool,
+    pub pathing: bool,
+}
+
+/// How Steam Audio determines whether a source is occluded.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OcclusionModel {
+    /// A single ray from the listener to the source.
+    Raycast,
+    /// Treats the source as a sphere and traces rays to points on it,
+    /// which allows for partial occlusion.
+    /// `num_samples` is limited to `PhononSettings::max_occlusion_samples`.
+    Volumetric { radius: f32, num_samples: u32 },
+}
+
+/// Decides which bevy_fmod `AudioSource`s are turned into Steam Audio sources.
+#[derive(Resource, Reflect, Debug, Clone, Default, Serialize, Deserialize)]
+#[reflect(Resource, Default)]
+pub enum PhononSourcePolicy {
+    /// Only sources with a `PhononSourceSettings` component are registered.
+    #[default]
+    OptIn,
+    /// Every source is registered, the given settings are inserted for sources without them.
+    All(PhononSourceSettings),
+}
+
+impl Default for PhononSourceSettings {
+    fn default() -> Self {
+        Self {
+            occlusion: Some(OcclusionModel::Raycast),
+            transmission_rays: 1,
+            reflections: true,
+            pathing: false,
+        }
+    }
+}
+
+impl PhononSourceSettings {
+    /// Only distance attenuation and air absorption.
+    pub fn direct_only() -> Self {
+        Self {
+            occlusion: None,
+            transmission_rays: 0,
+            reflections: false,
+            pathing: false,
+        }
+    }
+
+    /// The settings Steam Audio can actually use. Effects of stages that are disabled in `mode`
+    /// are disabled on the source as well, transmission is disabled without occlusion and
+    /// volumetric occlusion uses between 1 and `max_occlusion_samples` samples.
+    pub(crate) fn for_mode(&self, mode: &PhononSimulationMode, max_occlusion_samples: u32) -> Self {
+        let occlusion = self
+            .occlusion
+            .filter(|_| mode.direct.enabled)
+            .map(|occlusion| match occlusion {
+                OcclusionModel::Volumetric {
+                    radius,
+                    num_samples,
+                } => OcclusionModel::Volumetric {
+                    radius,
+                    num_samples: num_samples.min(max_occlusion_samples).max(1),
+                },
+                OcclusionModel::Raycast => OcclusionModel::Raycast,
+            });
+
+        Self {
+            occlusion,
+            transmission_rays: if occlusion.is_some() {
+                self.transmission_rays
+            } else {
+                0
+            },
+            reflections: self.reflections && mode.reflections.enabled,
+            pathing: self.pathing && mode.pathing.enabled,
+        }
+    }
+
+    /// Expects settings returned by `for_mode`.
+    pub(crate) fn apply(&self, source: &mut Source) {
+        source.set_distance_attenuation(DistanceAttenuationModel::Default);
+        source.set_air_absorption(AirAbsorptionModel::Default);
+
+        match self.occlusion {
+            Some(OcclusionModel::Raycast) => source.set_occlusion(),
+            Some(OcclusionModel::Volumetric {
+                radius,
+                num_samples,
+            }) => source.set_volumetric_occlusion(radius, num_samples),
+            None => source.disable_occlusion(),
+        }
+
+        if self.transmission_rays > 0 {
+            source.set_transmission(self.transmission_rays);
+        } else {
+            source.disable_transmission();
+        }
+
+        if self.reflections {
+            source.set_reflections();
+        } else {
+            source.disable_reflections();
+        }
+
+        if self.pathing {
+            source.set_pathing();
+        } else {
+            source.disable_pathing();
+        }
+    }
+}
+
+/// Copies the results of the latest finished simulation into `PhononSourceOutputs`.
+pub(crate) fn update_phonon_source_outputs(
+    results: Res<SimulationResults>,
+    mut source_query: Query<(Entity, &mut PhononSourceOutputs), With<PhononSource>>,
+) {
+    let results = results.read();
+
+    for (source_entity, mut outputs) in &mut source_query {
+        if let Some(source_outputs) = results.sources.get(&source_entity) {
+            outputs.set_if_neq(*source_outputs);
+        }
+    }
+}
+
+pub(crate) fn update_steam_audio_source(
+    mut inputs: ResMut<SimulationInputs>,
+    source_query: Query<(Entity, &GlobalTransform), With<PhononSource>>,
+) {
+    for (source_entity, source_transform) in &source_query {
+        inputs.transforms.insert(source_entity, *source_transform);
+    }
+}
+
+/// Creates Steam Audio sources and hands them to the simulation.
+#[derive(SystemParam)]
+pub(crate) struct SourceRegistry<'w> {
+    sim_res: Res<'w, SteamSimulation>,
+    mode: Res<'w, PhononSimulationMode>,
+    sources: ResMut<'w, PhononSources>,
+    inputs: ResMut<'w, SimulationInputs>,
+}
+
+impl<'w> SourceRegistry<'w> {
+    fn create_source(&self) -> Result<Source, String> {
+        simulation::create_source(&self.sim_res.simulator)
+    }
+
+    fn effective_settings(&self, settings: &PhononSourceSettings) -> PhononSourceSettings {
+        settings.for_mode(&self.mode, self.sim_res.max_occlusion_samples)
+    }
+
+    fn register(
+        &mut self,
+        entity: Entity,
+        source: Source,
+        settings: &PhononSourceSettings,
+        registered: RegisteredSource,
+    ) {
+        self.inputs
+            .add_source(entity, source, self.effective_settings(settings));
+        self.sources.insert(entity, registered);
+    }
+}
+
+/// bevy_fmod audio sources are converted to Steam Audio sources according to the
+/// `PhononSourcePolicy`.
+#[cfg(feature = "fmod")]
+pub(crate) fn register_phonon_sources(
+    mut audio_sources: Query<
+        (
+            Entity,
+            Ref<AudioSource>,
+            Option<&PhononSourceSettings>,
+            Option<&mut MissingPhononSpatializer>,
+        ),
+        Without<PhononSource>,
+    >,
+    mut commands: Commands,
+    mut registry: SourceRegistry,
+    policy: Res<PhononSourcePolicy>,
+    mut errors: EventWriter<PhononError>,
+) {
+    for (audio_entity, audio_source_fmod, source_settings, missing_spatializer) in
+        audio_sources.iter_mut()
+    {
+        let source_settings = match (source_settings, policy.as_ref()) {
+            (Some(source_settings), _) => source_settings.clone(),
+            (None, PhononSourcePolicy::All(default_settings)) => default_settings.clone(),
+            (None, PhononSourcePolicy::OptIn) => continue,
+        };
+
+        let is_retry = match missing_spatializer {
+            Some(mut missing_spatializer) => {
+                if !audio_source_fmod.is_changed() && !missing_spatializer.retry() {
+                    continue;
+                }
+                true
+            }
+            None => false,
+        };
+
+        let phonon_dsps = match get_phonon_spatializers(audio_source_fmod.event_instance) {
+            Ok(phonon_dsps) => phonon_dsps,
+            // Try again next frame, the event probably hasn't started yet.
+            Err(SpatializerError::NotReady) => continue,
+            Err(error) => {
+                // Without a spatializer, or if the DSP graph can not be read, searching again
+                // every frame would only repeat the error. It is reported once.
+                if !is_retry {
+                    commands
+                        .entity(audio_entity)
+                        .insert(MissingPhononSpatializer::new());
+                    errors.send(PhononError::Spatializer {
+                        entity: audio_entity,
+                        error,
+                    });
+                }
+                continue;
+            }
+        };
+
+        let source = match registry.create_source() {
+            Ok(source) => source,
+            Err(error) => {
+                errors.send(PhononError::SourceCreation {
+                    entity: audio_entity,
+                    error,
+                });
+                continue;
+            }
+        };
+
+        let source_address = fmod::add_source(&source);
+
+        for phonon_dsp in &phonon_dsps {
+            if let Err(error) = Spatializer(*phonon_dsp).set_simulation_outputs(source_address) {
+                errors.send(PhononError::Spatializer {
+                    entity: audio_entity,
+                    error: error.into(),
+                });
+            }
+        }
+
+        registry.register(
+            audio_entity,
+            source,
+            &source_settings,
+            RegisteredSource {
+                fmod: Some(FmodSource {
+                    address: source_address,
+                    dsps: phonon_dsps,
+                }),
+            },
+        );
+
+        commands
+            .entity(audio_entity)
+            .insert((
+                PhononSource,
+                source_settings,
+                PhononSourceOutputs::default(),
+            ))
+            .remove::<MissingPhononSpatializer>();
+    }
+}
+
+/// Every entity with `PhononSourceSettings` becomes a source for `PhononBackend::Headless`,
+/// there are no FMOD events or spatializers to look for.
+pub(crate) fn register_headless_sources(
+    mut commands: Commands,
+    mut registry: SourceRegistry,
+    source_query: Query<(Entity, &PhononSourceSettings), Without<PhononSource>>,
+    mut errors: EventWriter<PhononError>,
+) {
+    for (source_entity, source_settings) in &source_query {
+        let source = match registry.create_source() {
+            Ok(source) => source,
+            Err(error) => {
+                errors.send(PhononError::SourceCreation {
+                    entity: source_entity,
+                    error,
+                });
+                continue;
+            }
+        };
+
+        registry.register(
+            source_entity,
+            source,
+            source_settings,
+            RegisteredSource {
+                #[cfg(feature = "fmod")]
+                fmod: None,
+            },
+        );
+
+        commands
+            .entity(source_entity)
+            .insert((PhononSource, PhononSourceOutputs::default()));
+    }
+}
+
+/// Reconfigures already registered sources when their settings or the simulation mode change.
+pub(crate) fn update_phonon_source_settings(
+    sim_res: Res<SteamSimulation>,
+    sources: Res<PhononSources>,
+    mode: Res<PhononSimulationMode>,
+    mut inputs: ResMut<SimulationInputs>,
+    source_query: Query<(Entity, Ref<PhononSourceSettings>)>,
+) {
+    for (source_entity, source_settings) in &source_query {
+        if !source_settings.is_changed() && !mode.is_changed() {
+            continue;
+        }
+
+        if sources.contains_key(&source_entity) {
+            inputs.settings.insert(
+                source_entity,
+                source_settings.for_mode(&mode, sim_res.max_occlusion_samples),
+            );
+        }
+    }
+}
+
+/// Deregisters Steam Audio sources of entities that were despawned or lost their
+/// `AudioSource`. Otherwise the FMOD plugin would keep a handle to them and they would
+/// keep being simulated.
+/// A source that loses its `PhononSourceSettings` is deregistered with
+/// `PhononSourcePolicy::OptIn`, like in `PhononBackend::Headless`, and gets the settings of
+/// the policy again with `PhononSourcePolicy::All`.
+/// Runs before the registration, so a source that is replaced within a frame is
+/// registered again.
+#[cfg(feature = "fmod")]
+pub(crate) fn remove_phonon_sources(
+    mut commands: Commands,
+    mut sources: ResMut<PhononSources>,
+    mut inputs: ResMut<SimulationInputs>,
+    policy: Res<PhononSourcePolicy>,
+    mut removed_audio_sources: RemovedComponents<AudioSource>,
+    mut removed_settings: RemovedComponents<PhononSourceSettings>,
+) {
+    for entity in removed_audio_sources.read() {
+        deregister_source(entity, &mut commands, &mut sources, &mut inputs);
+    }
+
+    for entity in removed_settings.read() {
+        match policy.as_ref() {
+            PhononSourcePolicy::OptIn => {
+                deregister_source(entity, &mut commands, &mut sources, &mut inputs);
+            }
+            PhononSourcePolicy::All(default_settings) => {
+                if !sources.contains_key(&entity) {
+                    continue;
+                }
+                // `update_phonon_source_settings` applies them to the registered source.
+                if let Some(mut entity_commands) = commands.get_entity(entity) {
+                    entity_commands.insert(default_settings.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Deregisters the sources of `PhononBackend::Headless` whose entity was despawned or lost
+/// its `PhononSourceSettings`.
+pub(crate) fn remove_headless_sources(
+    mut commands: Commands,
+    mut sources: ResMut<PhononSources>,
+    mut inputs: ResMut<SimulationInputs>,
+    mut removed_settings: RemovedComponents<PhononSourceSettings>,
+) {
+    for entity in removed_settings.read() {
+        deregister_source(entity, &mut commands, &mut sources, &mut inputs);
+    }
+}
+
+fn deregister_source(
+    entity: Entity,
+    commands: &mut Commands,
+    sources: &mut PhononSources,
+    inputs: &mut SimulationInputs,
+) {
+    let Some(registered) = sources.remove(&entity) else {
+        return;
+    };
+
+    inputs.remove_source(entity);
+    registered.release();
+
+    // The entity might still exist and be registered again later.
+    if let Some(mut entity_commands) = commands.get_entity(entity) {
+        entity_commands.remove::<(PhononSource, PhononSourceOutputs)>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phonon_listener::PhononActiveListener;
+    use crate::phonon_plugin::headless_app;
+    use crate::settings::{PhononSettings, SimulationExecution};
+    use crate::simulation_mode::{PhononSimulationMode, StageMode};
+    #[cfg(feature = "fmod")]
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::{Duration, Instant};
+
+    const MAX_OCCLUSION_SAMPLES: u32 = 16;
+
+    fn volumetric(num_samples: u32) -> Option<OcclusionModel> {
+        Some(OcclusionModel::Volumetric {
+            radius: 1.0,
+            num_samples,
+        })
+    }
+
+    #[test]
+    fn settings_are_kept_when_every_stage_runs() {
+        let mode = PhononSimulationMode {
+            pathing: StageMode::every_frame(),
+            ..default()
+        };
+        let settings = PhononSourceSettings {
+            occlusion: volumetric(8),
+            transmission_rays: 3,
+            reflections: true,
+            pathing: true,
+        };
+
+        assert_eq!(settings.for_mode(&mode, MAX_OCCLUSION_SAMPLES), settings);
+    }
+
+    #[test]
+    fn disabled_stages_disable_their_effects() {
+        let settings = PhononSourceSettings {
+            pathing: true,
+            ..default()
+        };
+        let mode = PhononSimulationMode {
+            direct: StageMode::disabled(),
+            reflections: StageMode::disabled(),
+            pathing: StageMode::disabled(),
+        };
+
+        let effective = settings.for_mode(&mode, MAX_OCCLUSION_SAMPLES);
+        assert_eq!(effective.occlusion, None);
+        assert_eq!(effective.transmission_rays, 0);
+        assert!(!effective.reflections);
+        assert!(!effective.pathing);
+    }
+
+    #[test]
+    fn transmission_requires_occlusion() {
+        let settings = PhononSourceSettings {
+            occlusion: None,
+            transmission_rays: 4,
+            ..default()
+        };
+
+        let effective = settings.for_mode(&default(), MAX_OCCLUSION_SAMPLES);
+        assert_eq!(effective.transmission_rays, 0);
+        assert_eq!(
+            PhononSourceSettings::direct_only().for_mode(&default(), MAX_OCCLUSION_SAMPLES),
+            PhononSourceSettings::direct_only()
+        );
+    }
+
+    #[test]
+    fn volumetric_samples_are_limited() {
+        let effective = |num_samples| {
+            PhononSourceSettings {
+                occlusion: volumetric(num_samples),
+                ..default()
+            }
+            .for_mode(&default(), MAX_OCCLUSION_SAMPLES)
+            .occlusion
+        };
+
+        assert_eq!(effective(64), volumetric(MAX_OCCLUSION_SAMPLES));
+        assert_eq!(effective(0), volumetric(1));
+        assert_eq!(effective(4), volumetric(4));
+    }
+
+    #[cfg(feature = "fmod")]
+    #[test]
+    fn spatializer_search_is_retried() {
+        let mut missing_spatializer = MissingPhononSpatializer::new();
+
+        for _ in 0..3 {
+            for _ in 1..MissingPhononSpatializer::RETRY_FRAMES {
+                assert!(!missing_spatializer.retry());
+            }
+            assert!(missing_spatializer.retry());
+        }
+    }
+
+    /// A world with one registered FMOD source that loses its `PhononSourceSettings`.
+    #[cfg(feature = "fmod")]
+    fn remove_settings(policy: PhononSourcePolicy) -> (World, Entity) {
+        let mut world = World::new();
+        let source = world
+            .spawn((PhononSource, PhononSourceSettings::direct_only()))
+            .id();
+        let mut sources = PhononSources::default();
+        sources.insert(source, RegisteredSource { fmod: None });
+        world.insert_resource(sources);
+        world.insert_resource(SimulationInputs::default());
+        world.insert_resource(policy);
+
+        world.entity_mut(source).remove::<PhononSourceSettings>();
+        world.run_system_once(remove_phonon_sources);
+        (world, source)
+    }
+
+    #[cfg(feature = "fmod")]
+    #[test]
+    fn opted_in_sources_are_removed_with_their_settings() {
+        let (world, source) = remove_settings(PhononSourcePolicy::OptIn);
+
+        assert!(world.resource::<PhononSources>().is_empty());
+        assert!(!world.entity(source).contains::<PhononSource>());
+    }
+
+    #[cfg(feature = "fmod")]
+    #[test]
+    fn policy_settings_replace_removed_settings() {
+        let (world, source) = remove_settings(PhononSourcePolicy::All(default()));
+
+        assert!(world.resource::<PhononSources>().contains_key(&source));
+        assert_eq!(
+            world.get::<PhononSourceSettings>(source),
+            Some(&PhononSourceSettings::default())
+        );
+    }
+
+    #[test]
+    fn background_outputs_arrive() {
+        let mut app = headless_app(PhononSettings {
+            execution: SimulationExecution::Background,
+            simulation_mode: PhononSimulationMode::background(),
+            ..default()
+        });
+        app.world
+            .spawn((PhononActiveListener, TransformBundle::default()));
+        let source = app
+            .world
+            .spawn((
+                PhononSourceSettings::default(),
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, -20.0)),
+            ))
+            .id();
+
+        let started = Instant::now();
+        loop {
+            app.update();
+
+            let outputs = app.world.get::<PhononSourceOutputs>(source);
+            if outputs.is_some_and(|outputs| outputs.distance_attenuation < 1.0) {
+                break;
+            }
+
+            assert!(started.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
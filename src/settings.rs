@@ -0,0 +1,462 @@
+use crate::phonon_source::PhononSourcePolicy;
+use crate::simulation_mode::PhononSimulationMode;
+use bevy::reflect::{std_traits::ReflectDefault, Reflect};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The occlusion samples the simulators of the `steamaudio` crate have room for.
+/// `Context::create_simulator` does not take a limit, so sources can not use more.
+pub const MAX_OCCLUSION_SAMPLES: u32 = 16;
+
+/// Settings used to create the Steam Audio simulator and the FMOD plugin.
+/// These are read once when the `PhononPlugin` is built.
+#[derive(Reflect, Debug, Clone, Serialize, Deserialize)]
+#[reflect(Default)]
+pub struct PhononSettings {
+    /// By default the sampling rate is read from the FMOD mixer.
+    /// If an override is given it needs to be equal to the FMOD sampling rate.
+    pub sampling_rate: Option<u32>,
+    /// By default the frame size is read from the FMOD DSP buffer length.
+    /// If an override is given it needs to be equal to it.
+    pub frame_size: Option<u32>,
+    /// The most samples a source may use for volumetric occlusion, the actual amount is set
+    /// per source. Sources asking for more are limited to this.
+    /// At most `MAX_OCCLUSION_SAMPLES`.
+    pub max_occlusion_samples: u32,
+    pub reflections: ReflectionSettings,
+    /// Inserted as a resource, so it can be changed at runtime.
+    pub source_policy: PhononSourcePolicy,
+    pub execution: SimulationExecution,
+    /// Inserted as a resource, so it can be changed at runtime.
+    pub simulation_mode: PhononSimulationMode,
+    pub backend: PhononBackend,
+}
+
+/// What consumes the simulation results.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum PhononBackend {
+    /// The Steam Audio FMOD plugin, requires `FmodPlugin` and the `fmod` feature.
+    #[cfg(feature = "fmod")]
+    #[default]
+    Fmod,
+    /// No audio at all, for dedicated servers and tests. Geometry, queries and
+    /// `PhononSourceOutputs` work as usual. Every entity with `PhononSourceSettings`
+    /// becomes a source and the listener is the entity with `PhononActiveListener`.
+    /// The audio format is taken from the overrides, or 48 KHz with 1024 samples per frame.
+    /// The default without the `fmod` feature.
+    #[cfg_attr(not(feature = "fmod"), default)]
+    Headless,
+}
+
+/// Where the Steam Audio simulation runs.
+#[derive(Reflect, Debug, Clone, Default, Serialize, Deserialize)]
+#[reflect(Default)]
+pub enum SimulationExecution {
+    /// The simulation stages run in `PhononPlugin::schedule`, `PostUpdate` by default.
+    #[default]
+    MainThread,
+    /// A dedicated thread runs the simulation stages, the Bevy schedule never waits for it.
+    /// Stages without an interval run whenever the thread receives the inputs of a frame,
+    /// see `PhononSimulationMode::background` for rates that suit this better.
+    Background,
+}
+
+/// Settings for the reflection simulation.
+#[derive(Reflect, Debug, Clone, Serialize, Deserialize)]
+#[reflect(Default)]
+pub struct ReflectionSettings {
+    pub num_rays: u32,
+    pub num_bounces: u32,
+    /// Duration of the impulse responses in seconds.
+    pub duration: f32,
+    /// Ambisonic order of the impulse responses.
+    pub order: u32,
+    /// Rays that hit a surface closer than this distance (in meters) use this distance
+    /// instead when calculating the irradiance.
+    pub irradiance_min_distance: f32,
+}
+
+impl Default for PhononSettings {
+    fn default() -> Self {
+        Self {
+            sampling_rate: None,
+            frame_size: None,
+            max_occlusion_samples: 16,
+            reflections: ReflectionSettings::default(),
+            source_policy: PhononSourcePolicy::default(),
+            execution: SimulationExecution::default(),
+            simulation_mode: PhononSimulationMode::default(),
+            backend: PhononBackend::default(),
+        }
+    }
+}
+
+/// The audio format Steam Audio has to match, as used by the FMOD mixer.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioFormat {
+    pub sampling_rate: u32,
+    pub frame_size: u32,
+}
+
+impl Default for ReflectionSettings {
+    fn default() -> Self {
+        Self {
+            num_rays: 4096,
+            num_bounces: 16,
+            duration: 2.0,
+            order: 1,
+            irradiance_min_distance: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingsError {
+    ZeroSamplingRate,
+    ZeroFrameSize,
+    ZeroOcclusionSamples,
+    /// More than `MAX_OCCLUSION_SAMPLES`.
+    TooManyOcclusionSamples(u32),
+    ZeroReflectionRays,
+    ZeroReflectionBounces,
+    NonPositiveReflectionDuration(f32),
+    /// Steam Audio supports ambisonic orders 0 through 3.
+    UnsupportedAmbisonicOrder(u32),
+    /// Negative or NaN.
+    NegativeIrradianceMinDistance(f32),
+    SamplingRateMismatch {
+        requested: u32,
+        fmod: u32,
+    },
+    FrameSizeMismatch {
+        requested: u32,
+        fmod: u32,
+    },
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::ZeroSamplingRate => write!(f, "sampling rate must be non-zero"),
+            SettingsError::ZeroFrameSize => write!(f, "frame size must be non-zero"),
+            SettingsError::ZeroOcclusionSamples => {
+                write!(f, "max occlusion samples must be non-zero")
+            }
+            SettingsError::TooManyOcclusionSamples(samples) => write!(
+                f,
+                "max occlusion samples must be at most {MAX_OCCLUSION_SAMPLES}, got {samples}"
+            ),
+            SettingsError::ZeroReflectionRays => write!(f, "reflection ray count must be non-zero"),
+            SettingsError::ZeroReflectionBounces => {
+                write!(f, "reflection bounce count must be non-zero")
+            }
+            SettingsError::NonPositiveReflectionDuration(duration) => {
+                write!(f, "reflection duration must be positive, got {duration}")
+            }
+            SettingsError::UnsupportedAmbisonicOrder(order) => {
+                write!(f, "ambisonic order must be between 0 and 3, got {order}")
+            }
+            SettingsError::NegativeIrradianceMinDistance(distance) => {
+                write!(
+                    f,
+                    "irradiance min distance must not be negative, got {distance}"
+                )
+            }
+            SettingsError::SamplingRateMismatch { requested, fmod } => write!(
+                f,
+                "sampling rate override ({requested} Hz) differs from the FMOD mixer ({fmod} Hz)"
+            ),
+            SettingsError::FrameSizeMismatch { requested, fmod } => write!(
+                f,
+                "frame size override ({requested}) differs from the FMOD DSP buffer length ({fmod})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl PhononSettings {
+    /// Checks whether Steam Audio will accept these settings.
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if self.sampling_rate == Some(0) {
+            return Err(SettingsError::ZeroSamplingRate);
+        }
+        if self.frame_size == Some(0) {
+            return Err(SettingsError::ZeroFrameSize);
+        }
+        if self.max_occlusion_samples == 0 {
+            return Err(SettingsError::ZeroOcclusionSamples);
+        }
+        if self.max_occlusion_samples > MAX_OCCLUSION_SAMPLES {
+            return Err(SettingsError::TooManyOcclusionSamples(
+                self.max_occlusion_samples,
+            ));
+        }
+
+        self.reflections.validate()
+    }
+
+    /// The format used by `PhononBackend::Headless`, there is no mixer to match.
+    pub fn headless_format(&self) -> AudioFormat {
+        AudioFormat {
+            sampling_rate: self.sampling_rate.unwrap_or(48_000),
+            frame_size: self.frame_size.unwrap_or(1024),
+        }
+    }
+
+    /// Combines the overrides with the format FMOD is actually using.
+    /// Steam Audio would produce artifacts if the two disagree, so that is an error.
+    pub fn resolve_format(&self, fmod_format: AudioFormat) -> Result<AudioFormat, SettingsError> {
+        if let Some(requested) = self.sampling_rate {
+            if requested != fmod_format.sampling_rate {
+                return Err(SettingsError::SamplingRateMismatch {
+                    requested,
+                    fmod: fmod_format.sampling_rate,
+                });
+            }
+        }
+        if let Some(requested) = self.frame_size {
+            if requested != fmod_format.frame_size {
+                return Err(SettingsError::FrameSizeMismatch {
+                    requested,
+                    fmod: fmod_format.frame_size,
+                });
+            }
+        }
+
+        Ok(fmod_format)
+    }
+}
+
+impl ReflectionSettings {
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if self.num_rays == 0 {
+            return Err(SettingsError::ZeroReflectionRays);
+        }
+        if self.num_bounces == 0 {
+            return Err(SettingsError::ZeroReflectionBounces);
+        }
+        if self.duration.is_nan() || self.duration <= 0.0 {
+            return Err(SettingsError::NonPositiveReflectionDuration(self.duration));
+        }
+        if self.order > 3 {
+            return Err(SettingsError::UnsupportedAmbisonicOrder(self.order));
+        }
+        if self.irradiance_min_distance.is_nan() || self.irradiance_min_distance < 0.0 {
+            return Err(SettingsError::NegativeIrradianceMinDistance(
+                self.irradiance_min_distance,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_settings_are_valid() {
+        assert_eq!(PhononSettings::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn background_accepts_every_simulation_mode() {
+        for simulation_mode in [
+            PhononSimulationMode::default(),
+            PhononSimulationMode::background(),
+        ] {
+            let settings = PhononSettings {
+                execution: SimulationExecution::Background,
+                simulation_mode,
+                ..Default::default()
+            };
+            assert_eq!(settings.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let invalid = |settings: PhononSettings| settings.validate().unwrap_err();
+
+        assert_eq!(
+            invalid(PhononSettings {
+                sampling_rate: Some(0),
+                ..Default::default()
+            }),
+            SettingsError::ZeroSamplingRate
+        );
+        assert_eq!(
+            invalid(PhononSettings {
+                frame_size: Some(0),
+                ..Default::default()
+            }),
+            SettingsError::ZeroFrameSize
+        );
+        assert_eq!(
+            invalid(PhononSettings {
+                max_occlusion_samples: 0,
+                ..Default::default()
+            }),
+            SettingsError::ZeroOcclusionSamples
+        );
+        assert_eq!(
+            invalid(PhononSettings {
+                max_occlusion_samples: MAX_OCCLUSION_SAMPLES + 1,
+                ..Default::default()
+            }),
+            SettingsError::TooManyOcclusionSamples(MAX_OCCLUSION_SAMPLES + 1)
+        );
+    }
+
+    #[test]
+    fn invalid_reflection_settings_are_rejected() {
+        let invalid = |reflections: ReflectionSettings| {
+            PhononSettings {
+                reflections,
+                ..Default::default()
+            }
+            .validate()
+            .unwrap_err()
+        };
+
+        assert_eq!(
+            invalid(ReflectionSettings {
+                num_rays: 0,
+                ..Default::default()
+            }),
+            SettingsError::ZeroReflectionRays
+        );
+        assert_eq!(
+            invalid(ReflectionSettings {
+                num_bounces: 0,
+                ..Default::default()
+            }),
+            SettingsError::ZeroReflectionBounces
+        );
+        assert_eq!(
+            invalid(ReflectionSettings {
+                duration: 0.0,
+                ..Default::default()
+            }),
+            SettingsError::NonPositiveReflectionDuration(0.0)
+        );
+        assert!(matches!(
+            invalid(ReflectionSettings {
+                duration: f32::NAN,
+                ..Default::default()
+            }),
+            SettingsError::NonPositiveReflectionDuration(duration) if duration.is_nan()
+        ));
+        assert_eq!(
+            invalid(ReflectionSettings {
+                order: 4,
+                ..Default::default()
+            }),
+            SettingsError::UnsupportedAmbisonicOrder(4)
+        );
+        assert_eq!(
+            invalid(ReflectionSettings {
+                irradiance_min_distance: -1.0,
+                ..Default::default()
+            }),
+            SettingsError::NegativeIrradianceMinDistance(-1.0)
+        );
+        assert!(matches!(
+            invalid(ReflectionSettings {
+                irradiance_min_distance: f32::NAN,
+                ..Default::default()
+            }),
+            SettingsError::NegativeIrradianceMinDistance(distance) if distance.is_nan()
+        ));
+    }
+
+    #[test]
+    fn valid_limits_are_accepted() {
+        let settings = PhononSettings {
+            sampling_rate: Some(44_100),
+            frame_size: Some(512),
+            max_occlusion_samples: 1,
+            reflections: ReflectionSettings {
+                num_rays: 1,
+                num_bounces: 1,
+                duration: 0.1,
+                order: 3,
+                irradiance_min_distance: 0.0,
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(settings.validate(), Ok(()));
+
+        let most_samples = PhononSettings {
+            max_occlusion_samples: MAX_OCCLUSION_SAMPLES,
+            ..Default::default()
+        };
+        assert_eq!(most_samples.validate(), Ok(()));
+    }
+
+    const FMOD_FORMAT: AudioFormat = AudioFormat {
+        sampling_rate: 44_100,
+        frame_size: 512,
+    };
+
+    #[test]
+    fn format_falls_back_to_fmod() {
+        let settings = PhononSettings::default();
+
+        assert_eq!(settings.resolve_format(FMOD_FORMAT), Ok(FMOD_FORMAT));
+    }
+
+    #[test]
+    fn matching_overrides_are_accepted() {
+        let both = PhononSettings {
+            sampling_rate: Some(44_100),
+            frame_size: Some(512),
+            ..Default::default()
+        };
+        let sampling_rate = PhononSettings {
+            sampling_rate: Some(44_100),
+            ..Default::default()
+        };
+        let frame_size = PhononSettings {
+            frame_size: Some(512),
+            ..Default::default()
+        };
+
+        for settings in [both, sampling_rate, frame_size] {
+            assert_eq!(settings.resolve_format(FMOD_FORMAT), Ok(FMOD_FORMAT));
+        }
+    }
+
+    #[test]
+    fn mismatching_overrides_are_rejected() {
+        let sampling_rate = PhononSettings {
+            sampling_rate: Some(48_000),
+            ..Default::default()
+        };
+        assert_eq!(
+            sampling_rate.resolve_format(FMOD_FORMAT),
+            Err(SettingsError::SamplingRateMismatch {
+                requested: 48_000,
+                fmod: 44_100,
+            })
+        );
+
+        let frame_size = PhononSettings {
+            sampling_rate: Some(44_100),
+            frame_size: Some(1024),
+            ..Default::default()
+        };
+        assert_eq!(
+            frame_size.resolve_format(FMOD_FORMAT),
+            Err(SettingsError::FrameSizeMismatch {
+                requested: 1024,
+                fmod: 512,
+            })
+        );
+    }
+}
@@ -0,0 +1,451 @@
+use crate::error::PhononError;
+use crate::phonon_plugin::SteamSimulation;
+use crate::phonon_source::{PhononSourceOutputs, PhononSourceSettings};
+use crate::settings::{AudioFormat, ReflectionSettings};
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use steamaudio::context::Context;
+use steamaudio::geometry::Orientation;
+use steamaudio::scene::Scene;
+use steamaudio::simulation::{Simulator, Source};
+
+/// Changes to the listeners and sources since the last commit.
+/// The systems in `PhononSet::Register` and `PhononSet::SyncTransforms` only record them,
+/// `SimulationState::commit` applies them on the thread that runs the simulation,
+/// so the simulator is never changed while a stage runs.
+#[derive(Resource, Default)]
+pub(crate) struct SimulationInputs {
+    /// The listener the FMOD plugin uses, see `PhononListener`.
+    pub(crate) listener: Option<(Entity, GlobalTransform)>,
+    /// All other `PhononActiveListener`s, each gets its own simulator.
+    pub(crate) extra_listeners: Option<HashMap<Entity, GlobalTransform>>,
+    added: Vec<(Entity, Source)>,
+    removed: Vec<Entity>,
+    /// Already adjusted to the `PhononSimulationMode`.
+    pub(crate) settings: HashMap<Entity, PhononSourceSettings>,
+    pub(crate) transforms: HashMap<Entity, GlobalTransform>,
+}
+
+impl SimulationInputs {
+    pub(crate) fn add_source(
+        &mut self,
+        entity: Entity,
+        source: Source,
+        settings: PhononSourceSettings,
+    ) {
+        self.added.push((entity, source));
+        self.settings.insert(entity, settings);
+    }
+
+    pub(crate) fn remove_source(&mut self, entity: Entity) {
+        // A source that was never handed over is simply dropped.
+        self.added.retain(|(added, _)| *added != entity);
+        self.settings.remove(&entity);
+        self.transforms.remove(&entity);
+        self.removed.push(entity);
+    }
+
+    /// Adds inputs that were recorded after these, before these were applied.
+    pub(crate) fn merge(&mut self, newer: SimulationInputs) {
+        for entity in newer.removed {
+            self.remove_source(entity);
+        }
+        self.added.extend(newer.added);
+        self.settings.extend(newer.settings);
+        self.transforms.extend(newer.transforms);
+        self.listener = newer.listener.or(self.listener);
+        self.extra_listeners = newer.extra_listeners.or(self.extra_listeners.take());
+    }
+}
+
+/// Serializes commits of the root scene with everything else that uses the scene from
+/// another thread: geometry changes on the main thread and `PhononQueries`.
+/// Holds the number of commits so far.
+#[derive(Default)]
+pub(crate) struct SceneLock(Mutex<u64>);
+
+impl SceneLock {
+    pub(crate) fn lock(&self) -> MutexGuard<'_, u64> {
+        self.0.lock().unwrap()
+    }
+
+    fn commit(&self, scene: &Scene) {
+        let mut commits = self.lock();
+        scene.commit();
+        *commits += 1;
+    }
+}
+
+/// Creates simulators on the root scene, for the main listener and for every additional
+/// `PhononActiveListener`.
+#[derive(Clone)]
+pub(crate) struct SimulatorFactory {
+    pub(crate) context: Context,
+    pub(crate) scene: Scene,
+    pub(crate) format: AudioFormat,
+    pub(crate) reflections: ReflectionSettings,
+}
+
+impl SimulatorFactory {
+    pub(crate) fn create(&self) -> Result<Simulator, PhononError> {
+        let mut simulator = self
+            .context
+            .create_simulator(self.format.sampling_rate, self.format.frame_size)
+            .map_err(|error| PhononError::SimulatorCreation(format!("{error:?}")))?;
+        simulator.set_scene(&self.scene);
+
+        let reflections = &self.reflections;
+        simulator.set_reflections(
+            reflections.num_rays,
+            reflections.num_bounces,
+            reflections.duration,
+            reflections.order,
+            reflections.irradiance_min_distance,
+        );
+
+        Ok(simulator)
+    }
+}
+
+/// Outputs of a finished simulation.
+#[derive(Default)]
+pub(crate) struct SimulationOutputs {
+    /// As heard by the main listener.
+    pub(crate) sources: HashMap<Entity, PhononSourceOutputs>,
+    /// As heard by each listener, including the main one.
+    pub(crate) listeners: HashMap<Entity, HashMap<Entity, PhononSourceOutputs>>,
+}
+
+/// Outputs of the latest finished simulation. The simulation fills a buffer of its own and
+/// swaps it in once all stages are done, so readers never see a half-written result.
+#[derive(Resource, Clone, Default)]
+pub(crate) struct SimulationResults(Arc<Mutex<SimulationOutputs>>);
+
+impl SimulationResults {
+    pub(crate) fn read(&self) -> MutexGuard<'_, SimulationOutputs> {
+        self.0.lock().unwrap()
+    }
+
+    /// `back` receives the previous results, so its allocation is reused.
+    pub(crate) fn publish(&self, back: &mut SimulationOutputs) {
+        std::mem::swap(&mut *self.read(), back);
+    }
+}
+
+/// A simulator and the sources in it, simulated for a single listener.
+struct ListenerSimulation {
+    simulator: Simulator,
+    sources: HashMap<Entity, Source>,
+}
+
+impl ListenerSimulation {
+    fn new(simulator: Simulator) -> Self {
+        Self {
+            simulator,
+            sources: HashMap::new(),
+        }
+    }
+
+    fn insert_source(&mut self, entity: Entity, source: Source) {
+        self.simulator.add_source(&source);
+        self.sources.insert(entity, source);
+    }
+
+    fn remove_source(&mut self, entity: Entity) {
+        if let Some(source) = self.sources.remove(&entity) {
+            self.simulator.remove_source(&source);
+        }
+    }
+
+    fn read_outputs(&self, outputs: &mut HashMap<Entity, PhononSourceOutputs>) {
+        outputs.clear();
+        outputs.extend(
+            self.sources
+                .iter()
+                .map(|(entity, source)| (*entity, PhononSourceOutputs::from(source))),
+        );
+    }
+}
+
+/// The simulators and the sources in them. Owned by whichever thread runs the simulation,
+/// which is the only one that commits the simulators or the scene.
+#[derive(Resource)]
+pub(crate) struct SimulationState {
+    factory: SimulatorFactory,
+    scene_lock: Arc<SceneLock>,
+    listener: Option<Entity>,
+    main: ListenerSimulation,
+    extra: HashMap<Entity, ListenerSimulation>,
+    /// Kept to create the sources of listeners that are added later.
+    settings: HashMap<Entity, PhononSourceSettings>,
+    transforms: HashMap<Entity, GlobalTransform>,
+}
+
+impl SimulationState {
+    pub(crate) fn new(steam_simulation: &SteamSimulation) -> Self {
+        Self {
+            factory: steam_simulation.simulator_factory.clone(),
+            scene_lock: steam_simulation.scene_lock.clone(),
+            listener: None,
+            main: ListenerSimulation::new(steam_simulation.simulator.clone()),
+            extra: HashMap::new(),
+            settings: HashMap::new(),
+            transforms: HashMap::new(),
+        }
+    }
+
+    fn simulations(&self) -> impl Iterator<Item = &ListenerSimulation> {
+        std::iter::once(&self.main).chain(self.extra.values())
+    }
+
+    fn simulations_mut(&mut self) -> impl Iterator<Item = &mut ListenerSimulation> {
+        std::iter::once(&mut self.main).chain(self.extra.values_mut())
+    }
+
+    /// Makes geometry changes visible to the queries. The simulators pick them up with
+    /// their next `commit`.
+    pub(crate) fn commit_scene(&self) {
+        self.scene_lock.commit(&self.factory.scene);
+    }
+
+    /// Applies the inputs and commits them together with the latest scene, each simulator
+    /// once. Must not be called while a stage runs.
+    pub(crate) fn commit(&mut self, inputs: SimulationInputs) {
+        for entity in inputs.removed {
+            self.settings.remove(&entity);
+            self.transforms.remove(&entity);
+
+            for simulation in self.simulations_mut() {
+                simulation.remove_source(entity);
+            }
+        }
+
+        for (entity, source) in inputs.added {
+            self.main.insert_source(entity, source);
+
+            for (listener, simulation) in &mut self.extra {
+                match create_source(&simulation.simulator) {
+                    Ok(source) => simulation.insert_source(entity, source),
+                    Err(error) => error!("No source {entity:?} for listener {listener:?}: {error}"),
+                }
+            }
+        }
+
+        for (entity, settings) in inputs.settings {
+            for simulation in self.simulations_mut() {
+                if let Some(source) = simulation.sources.get_mut(&entity) {
+                    settings.apply(source);
+                }
+            }
+            self.settings.insert(entity, settings);
+        }
+        for (entity, transform) in inputs.transforms {
+            for simulation in self.simulations_mut() {
+                if let Some(source) = simulation.sources.get_mut(&entity) {
+                    source.set_source(orientation(&transform));
+                }
+            }
+            self.transforms.insert(entity, transform);
+        }
+
+        if let Some((entity, transform)) = inputs.listener {
+            self.listener = Some(entity);
+            self.main.simulator.set_listener(orientation(&transform));
+        }
+        if let Some(extra_listeners) = inputs.extra_listeners {
+            self.update_extra_listeners(extra_listeners);
+        }
+
+        for simulation in self.simulations() {
+            simulation.simulator.commit();
+        }
+    }
+
+    fn update_extra_listeners(&mut self, listeners: HashMap<Entity, GlobalTransform>) {
+        self.extra
+            .retain(|listener, _| listeners.contains_key(listener));
+
+        for (listener, transform) in listeners {
+            if !self.extra.contains_key(&listener) {
+                match self.create_listener_simulation() {
+                    Ok(simulation) => {
+                        self.extra.insert(listener, simulation);
+                    }
+                    Err(error) => {
+                        error!("Listener {listener:?} can not be simulated: {error}");
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(simulation) = self.extra.get(&listener) {
+                simulation.simulator.set_listener(orientation(&transform));
+            }
+        }
+    }
+
+    /// A simulator with all current sources.
+    fn create_listener_simulation(&self) -> Result<ListenerSimulation, PhononError> {
+        let mut simulation = ListenerSimulation::new(self.factory.create()?);
+
+        for (entity, settings) in &self.settings {
+            let mut source = create_source(&simulation.simulator).map_err(|error| {
+                PhononError::SourceCreation {
+                    entity: *entity,
+                    error,
+                }
+            })?;
+            settings.apply(&mut source);
+            if let Some(transform) = self.transforms.get(entity) {
+                source.set_source(orientation(transform));
+            }
+            simulation.insert_source(*entity, source);
+        }
+
+        Ok(simulation)
+    }
+
+    pub(crate) fn run_direct(&self) {
+        for simulation in self.simulations() {
+            simulation.simulator.run_direct();
+        }
+    }
+
+    pub(crate) fn run_reflections(&self) {
+        for simulation in self.simulations() {
+            simulation.simulator.run_reflections();
+        }
+    }
+
+    pub(crate) fn run_pathing(&self) {
+        for simulation in self.simulations() {
+            simulation.simulator.run_pathing();
+        }
+    }
+
+    /// Replaces the contents of `outputs` with the latest outputs of every source.
+    pub(crate) fn read_outputs(&self, outputs: &mut SimulationOutputs) {
+        self.main.read_outputs(&mut outputs.sources);
+
+        outputs.listeners.retain(|listener, _| {
+            Some(*listener) == self.listener || self.extra.contains_key(listener)
+        });
+        if let Some(listener) = self.listener {
+            let listener_outputs = outputs.listeners.entry(listener).or_default();
+            listener_outputs.clone_from(&outputs.sources);
+        }
+        for (listener, simulation) in &self.extra {
+            simulation.read_outputs(outputs.listeners.entry(*listener).or_default());
+        }
+    }
+}
+
+/// The source is only added to the simulator by `ListenerSimulation::insert_source`,
+/// creating it does not interfere with a running simulation.
+pub(crate) fn create_source(simulator: &Simulator) -> Result<Source, String> {
+    let mut source = simulator
+        .create_source(false)
+        .map_err(|error| format!("{error:?}"))?;
+    source.set_active(true);
+
+    Ok(source)
+}
+
+fn orientation(transform: &GlobalTransform) -> Orientation {
+    let (_scale, rotation, translation) = transform.to_scale_rotation_translation();
+
+    Orientation {
+        translation,
+        rotation,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phonon_listener::PhononActiveListener;
+    use crate::phonon_plugin::headless_app;
+    use crate::phonon_source::PhononSources;
+    use crate::settings::PhononSettings;
+
+    fn simulated_sources(app: &App) -> Vec<Entity> {
+        let mut sources = app
+            .world
+            .resource::<SimulationState>()
+            .main
+            .sources
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        sources.sort();
+        sources
+    }
+
+    #[test]
+    fn despawned_sources_are_removed() {
+        let mut app = headless_app(PhononSettings::default());
+        app.world
+            .spawn((PhononActiveListener, TransformBundle::default()));
+
+        for _ in 0..5 {
+            let mut sources = (0..1000)
+                .map(|index| {
+                    let translation = Vec3::new(index as f32, 0.0, -5.0);
+                    app.world
+                        .spawn((
+                            PhononSourceSettings::default(),
+                            TransformBundle::from_transform(Transform::from_translation(
+                                translation,
+                            )),
+                        ))
+                        .id()
+                })
+                .collect::<Vec<_>>();
+            sources.sort();
+            app.update();
+            assert_eq!(app.world.resource::<PhononSources>().len(), sources.len());
+            assert_eq!(simulated_sources(&app), sources);
+
+            for source in sources {
+                app.world.despawn(source);
+            }
+            app.update();
+            assert!(app.world.resource::<PhononSources>().is_empty());
+            assert!(simulated_sources(&app).is_empty());
+            assert!(app
+                .world
+                .resource::<SimulationResults>()
+                .read()
+                .sources
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn sources_without_settings_are_removed() {
+        let mut app = headless_app(PhononSettings::default());
+        app.world
+            .spawn((PhononActiveListener, TransformBundle::default()));
+        let source = app
+            .world
+            .spawn((PhononSourceSettings::default(), TransformBundle::default()))
+            .id();
+        app.update();
+        assert_eq!(simulated_sources(&app), [source]);
+
+        app.world
+            .entity_mut(source)
+            .remove::<PhononSourceSettings>();
+        app.update();
+        assert!(simulated_sources(&app).is_empty());
+        assert!(!app.world.entity(source).contains::<PhononSourceOutputs>());
+
+        // Registered again like a new source.
+        app.world
+            .entity_mut(source)
+            .insert(PhononSourceSettings::default());
+        app.update();
+        assert_eq!(simulated_sources(&app), [source]);
+    }
+}
@@ -0,0 +1,188 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Which simulation stages run and how often. Can be changed at runtime, the sources
+/// are reconfigured accordingly.
+#[derive(Resource, Reflect, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[reflect(Resource, Default)]
+pub struct PhononSimulationMode {
+    /// Distance attenuation, air absorption, occlusion and transmission.
+    pub direct: StageMode,
+    pub reflections: StageMode,
+    pub pathing: StageMode,
+}
+
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StageMode {
+    pub enabled: bool,
+    /// Minimum time between two runs of this stage.
+    /// `Duration::ZERO` runs it every frame, with `SimulationExecution::Background` whenever
+    /// the inputs of a frame arrive.
+    pub interval: Duration,
+}
+
+impl Default for PhononSimulationMode {
+    fn default() -> Self {
+        Self {
+            direct: StageMode::every_frame(),
+            reflections: StageMode::every_frame(),
+            pathing: StageMode::disabled(),
+        }
+    }
+}
+
+impl PhononSimulationMode {
+    /// Rates that suit `SimulationExecution::Background`: direct simulation at 60 Hz and
+    /// reflections at 10 Hz, pathing disabled.
+    pub fn background() -> Self {
+        Self {
+            direct: StageMode::at_rate(60.0),
+            reflections: StageMode::at_rate(10.0),
+            pathing: StageMode::disabled(),
+        }
+    }
+}
+
+impl StageMode {
+    pub fn every_frame() -> Self {
+        Self {
+            enabled: true,
+            interval: Duration::ZERO,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            interval: Duration::ZERO,
+        }
+    }
+
+    /// Runs at most `hz` times per second. A rate of zero or less never runs the stage.
+    pub fn at_rate(hz: f32) -> Self {
+        if hz.is_nan() || hz <= 0.0 {
+            return Self::disabled();
+        }
+
+        Self {
+            enabled: true,
+            // Tiny rates do not fit in a `Duration`, they run once at most.
+            interval: Duration::try_from_secs_f32(1.0 / hz).unwrap_or(Duration::MAX),
+        }
+    }
+}
+
+/// Keeps track of when each stage last ran.
+#[derive(Default)]
+pub(crate) struct StageTimer {
+    last_run: Option<Instant>,
+}
+
+impl StageTimer {
+    /// Returns true and restarts the timer if the stage should run now.
+    pub(crate) fn tick(&mut self, mode: &StageMode, now: Instant) -> bool {
+        if !mode.enabled {
+            return false;
+        }
+
+        let due = match self.last_run {
+            Some(last_run) => now.duration_since(last_run) >= mode.interval,
+            None => true,
+        };
+
+        if due {
+            self.last_run = Some(now);
+        }
+
+        due
+    }
+
+    /// When this stage should run next, `None` if it only runs on demand or its interval
+    /// reaches past what an `Instant` can hold.
+    pub(crate) fn next_run(&self, mode: &StageMode) -> Option<Instant> {
+        if !mode.enabled || mode.interval.is_zero() {
+            return None;
+        }
+
+        match self.last_run {
+            Some(last_run) => last_run.checked_add(mode.interval),
+            None => Some(Instant::now()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct StageTimers {
+    pub(crate) direct: StageTimer,
+    pub(crate) reflections: StageTimer,
+    pub(crate) pathing: StageTimer,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiny_rates_run_once() {
+        let mode = StageMode::at_rate(f32::MIN_POSITIVE);
+        assert_eq!(mode.interval, Duration::MAX);
+
+        let mut timer = StageTimer::default();
+        assert!(timer.next_run(&mode).is_some());
+        let now = Instant::now();
+        assert!(timer.tick(&mode, now));
+
+        assert_eq!(timer.next_run(&mode), None);
+        assert!(!timer.tick(&mode, now + Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn stages_skip_until_their_interval_passed() {
+        let mode = StageMode::at_rate(10.0);
+        assert_eq!(mode.interval, Duration::from_millis(100));
+
+        let mut timer = StageTimer::default();
+        let start = Instant::now();
+        assert!(timer.tick(&mode, start));
+        assert!(!timer.tick(&mode, start + Duration::from_millis(50)));
+        assert!(!timer.tick(&mode, start + Duration::from_millis(99)));
+        assert_eq!(
+            timer.next_run(&mode),
+            Some(start + Duration::from_millis(100))
+        );
+
+        // The interval counts from the last run, not from the skipped ticks.
+        assert!(timer.tick(&mode, start + Duration::from_millis(130)));
+        assert!(!timer.tick(&mode, start + Duration::from_millis(200)));
+        assert!(timer.tick(&mode, start + Duration::from_millis(230)));
+    }
+
+    #[test]
+    fn zero_interval_runs_every_time() {
+        let mode = StageMode::every_frame();
+        let mut timer = StageTimer::default();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(timer.tick(&mode, now));
+        }
+        assert_eq!(timer.next_run(&mode), None);
+    }
+
+    #[test]
+    fn disabled_stages_never_run() {
+        let mut timer = StageTimer::default();
+        let now = Instant::now();
+
+        for mode in [
+            StageMode::disabled(),
+            StageMode::at_rate(0.0),
+            StageMode::at_rate(-1.0),
+        ] {
+            assert!(!mode.enabled);
+            assert!(!timer.tick(&mode, now));
+            assert_eq!(timer.next_run(&mode), None);
+        }
+    }
+}
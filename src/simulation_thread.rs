@@ -0,0 +1,287 @@
+use crate::error::PhononError;
+use crate::phonon_plugin::SteamSimulation;
+use crate::simulation::{SimulationInputs, SimulationOutputs, SimulationResults, SimulationState};
+use crate::simulation_mode::{PhononSimulationMode, StageMode, StageTimer, StageTimers};
+use bevy::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// State shared between the main thread and the simulation thread.
+struct Shared {
+    /// Inputs handed over by the main thread and not picked up yet.
+    /// Inputs of several frames are merged, so no change gets lost.
+    inputs: Mutex<Option<SimulationInputs>>,
+    /// Set by the main thread when the geometry changed.
+    scene_dirty: AtomicBool,
+    /// Copy of the `PhononSimulationMode` resource.
+    mode: Mutex<PhononSimulationMode>,
+    stop: AtomicBool,
+}
+
+/// Runs the Steam Audio simulation on a dedicated thread, so a slow reflection
+/// simulation does not stall the Bevy schedule.
+///
+/// The main thread only records the changes to the listener, sources and geometry and hands
+/// them over. The simulation thread owns the simulator: it commits the scene and applies the
+/// inputs in between runs, so it is the only thread that commits and every stage runs on
+/// a consistent snapshot. Finished outputs are published to `SimulationResults`.
+///
+/// Steam Audio allows simulations to run on another thread as long as the simulators and
+/// the scene are not committed while a stage runs. Once the thread is started, the main
+/// thread only creates sources, which are added to a simulator by this thread, and changes
+/// the geometry under the `SceneLock`.
+#[derive(Resource)]
+pub(crate) struct SimulationThread {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SimulationThread {
+    pub(crate) fn spawn(
+        state: SimulationState,
+        results: SimulationResults,
+        mode: PhononSimulationMode,
+    ) -> Result<Self, PhononError> {
+        let shared = Arc::new(Shared {
+            inputs: Mutex::new(None),
+            scene_dirty: AtomicBool::new(false),
+            mode: Mutex::new(mode),
+            stop: AtomicBool::new(false),
+        });
+
+        let thread_shared = shared.clone();
+        let handle = std::thread::Builder::new()
+            .name("steam audio simulation".to_string())
+            .spawn(move || run_simulation_thread(&thread_shared, state, &results))
+            .map_err(|error| PhononError::SimulationThreadSpawn(error.to_string()))?;
+
+        Ok(Self {
+            shared,
+            handle: Some(handle),
+        })
+    }
+
+    /// Only waits for the simulation thread to take the previous inputs, never for a run.
+    pub(crate) fn submit(&self, inputs: SimulationInputs) {
+        {
+            let mut pending = self.shared.inputs.lock().unwrap();
+            match pending.as_mut() {
+                Some(pending) => pending.merge(inputs),
+                None => *pending = Some(inputs),
+            }
+        }
+        self.wake();
+    }
+
+    fn request_scene_commit(&self) {
+        self.shared.scene_dirty.store(true, Ordering::Release);
+        self.wake();
+    }
+
+    fn set_mode(&self, mode: PhononSimulationMode) {
+        *self.shared.mode.lock().unwrap() = mode;
+        self.wake();
+    }
+
+    fn wake(&self) {
+        if let Some(handle) = &self.handle {
+            handle.thread().unpark();
+        }
+    }
+}
+
+impl Drop for SimulationThread {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_simulation_thread(shared: &Shared, mut state: SimulationState, results: &SimulationResults) {
+    let mut timers = StageTimers::default();
+    let mut outputs = SimulationOutputs::default();
+
+    while !shared.stop.load(Ordering::Acquire) {
+        // Steam Audio does not allow committing while a simulation is running,
+        // so this thread does all commits, in between runs.
+        let scene_changed = shared.scene_dirty.swap(false, Ordering::AcqRel);
+        if scene_changed {
+            state.commit_scene();
+        }
+
+        let inputs = shared.inputs.lock().unwrap().take();
+        let committed = inputs.is_some() || scene_changed;
+        if committed {
+            state.commit(inputs.unwrap_or_default());
+        }
+
+        let mode = shared.mode.lock().unwrap().clone();
+        let now = Instant::now();
+        let mut simulated = false;
+
+        if is_due(&mut timers.direct, &mode.direct, committed, now) {
+            state.run_direct();
+            simulated = true;
+        }
+        if is_due(&mut timers.reflections, &mode.reflections, committed, now) {
+            state.run_reflections();
+            simulated = true;
+        }
+        if is_due(&mut timers.pathing, &mode.pathing, committed, now) {
+            state.run_pathing();
+            simulated = true;
+        }
+
+        if simulated {
+            state.read_outputs(&mut outputs);
+            results.publish(&mut outputs);
+        }
+
+        let next_run = [
+            timers.direct.next_run(&mode.direct),
+            timers.reflections.next_run(&mode.reflections),
+            timers.pathing.next_run(&mode.pathing),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+
+        // Woken up early by new inputs, a scene change, a mode change or shutdown.
+        match next_run {
+            Some(next_run) => {
+                std::thread::park_timeout(next_run.saturating_duration_since(Instant::now()))
+            }
+            None => std::thread::park(),
+        }
+    }
+}
+
+/// Stages without an interval run once per commit.
+fn is_due(timer: &mut StageTimer, mode: &StageMode, committed: bool, now: Instant) -> bool {
+    (committed || !mode.interval.is_zero()) && timer.tick(mode, now)
+}
+
+/// Used instead of `commit_audio_scene` when the simulation runs in the background.
+pub(crate) fn request_scene_commit(
+    mut sim_res: ResMut<SteamSimulation>,
+    simulation_thread: Res<SimulationThread>,
+) {
+    if std::mem::take(&mut sim_res.scene_dirty) {
+        simulation_thread.request_scene_commit();
+    }
+}
+
+/// Used instead of `update_steam_audio` when the simulation runs in the background.
+pub(crate) fn commit_steam_audio(
+    simulation_thread: Res<SimulationThread>,
+    mut inputs: ResMut<SimulationInputs>,
+    mode: Res<PhononSimulationMode>,
+) {
+    if mode.is_changed() {
+        simulation_thread.set_mode(mode.clone());
+    }
+
+    // The listener and sources have been updated, the simulation thread applies them
+    // before its next run.
+    simulation_thread.submit(std::mem::take(&mut *inputs));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phonon_listener::PhononActiveListener;
+    use crate::phonon_mesh::material::materials;
+    use crate::phonon_mesh::NeedsAudioMesh;
+    use crate::phonon_plugin::headless_app;
+    use crate::phonon_source::PhononSourceSettings;
+    use crate::settings::{PhononSettings, SimulationExecution};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// A closed room with a listener and sources, simulated in the background.
+    fn background_room() -> App {
+        let mut app = headless_app(PhononSettings {
+            execution: SimulationExecution::Background,
+            simulation_mode: PhononSimulationMode::background(),
+            ..default()
+        });
+
+        let room = app
+            .world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Cuboid::new(20.0, 10.0, 20.0));
+        app.world.spawn((
+            room,
+            NeedsAudioMesh::new(materials::CONCRETE),
+            TransformBundle::default(),
+        ));
+        app.world
+            .spawn((PhononActiveListener, TransformBundle::default()));
+        for index in 0..8 {
+            app.world.spawn((
+                PhononSourceSettings::default(),
+                TransformBundle::from_transform(Transform::from_xyz(index as f32, 0.0, -5.0)),
+            ));
+        }
+
+        app.update();
+        app
+    }
+
+    #[test]
+    fn stages_without_interval_wait_for_inputs() {
+        let every_frame = StageMode::every_frame();
+        let at_rate = StageMode::at_rate(10.0);
+        let mut timer = StageTimer::default();
+        let start = Instant::now();
+
+        assert!(!is_due(&mut timer, &every_frame, false, start));
+        assert!(is_due(&mut timer, &every_frame, true, start));
+        assert!(is_due(&mut timer, &every_frame, true, start));
+
+        let mut timer = StageTimer::default();
+        assert!(is_due(&mut timer, &at_rate, false, start));
+        assert!(!is_due(&mut timer, &at_rate, true, start));
+        assert!(is_due(
+            &mut timer,
+            &at_rate,
+            false,
+            start + Duration::from_millis(100)
+        ));
+    }
+
+    #[test]
+    fn frames_do_not_wait_for_a_run() {
+        let mut app = background_room();
+        let shared = app.world.resource::<SimulationThread>().shared.clone();
+        let (held_sender, held) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+
+        // The simulation thread reads the mode before every run, so holding it keeps the
+        // thread from simulating. Only gives up if the frames below wait for that run.
+        let holder = std::thread::spawn(move || {
+            let _mode = shared.mode.lock().unwrap();
+            held_sender.send(()).unwrap();
+            released.recv_timeout(Duration::from_secs(30)).is_err()
+        });
+        held.recv().unwrap();
+
+        for index in 0..10 {
+            let mut listener = app
+                .world
+                .query_filtered::<&mut Transform, With<PhononActiveListener>>();
+            listener.single_mut(&mut app.world).translation.x = index as f32;
+            app.update();
+        }
+
+        let _ = release.send(());
+        let timed_out = holder.join().unwrap();
+        assert!(!timed_out, "a frame waited for the simulation");
+    }
+}
@@ -0,0 +1,97 @@
+pub mod params;
+
+use libfmod::{ChannelGroup, Dsp, EventInstance};
+use std::fmt;
+
+/// Name the Steam Audio FMOD plugin reports for its spatializer DSP.
+const SPATIALIZER_NAME: &str = "Steam Audio Spatializer";
+
+#[derive(Debug)]
+pub enum SpatializerError {
+    /// The channel group of the event instance has not been created yet,
+    /// this happens until the event has started. Try again later.
+    NotReady,
+    /// The DSP graph of the event does not contain a Steam Audio Spatializer.
+    NotFound,
+    Fmod(libfmod::Error),
+    /// The DSP reported a value that is not part of the parameter's enum,
+    /// e.g. from a newer version of the plugin.
+    UnexpectedValue(i32),
+}
+
+impl fmt::Display for SpatializerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpatializerError::NotReady => write!(f, "event instance has not started yet"),
+            SpatializerError::NotFound => {
+                write!(f, "event does not contain a {SPATIALIZER_NAME}")
+            }
+            SpatializerError::Fmod(error) => write!(f, "FMOD error: {error:?}"),
+            SpatializerError::UnexpectedValue(value) => {
+                write!(f, "{SPATIALIZER_NAME} reported an unknown value {value}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpatializerError {}
+
+impl From<libfmod::Error> for SpatializerError {
+    fn from(error: libfmod::Error) -> Self {
+        SpatializerError::Fmod(error)
+    }
+}
+
+/// The goal here is to find the Steam Audio Spatializer DSPs associated with an instance.
+/// This way we can later set their parameters.
+/// The DSPs can basically be anywhere in the DSP graph: on the master track, which is the
+/// channel group of the instance itself, or on any of the tracks below it, which are its
+/// child channel groups. Events with multiple spatialized tracks have one spatializer per track.
+pub fn get_phonon_spatializers(instance: EventInstance) -> Result<Vec<Dsp>, SpatializerError> {
+    let channel_group = match instance.get_channel_group() {
+        Ok(channel_group) => channel_group,
+        Err(error) if is_not_loaded(&error) => return Err(SpatializerError::NotReady),
+        Err(error) => return Err(SpatializerError::Fmod(error)),
+    };
+
+    let mut spatializers = Vec::new();
+    collect_spatializers(channel_group, &mut spatializers)?;
+
+    if spatializers.is_empty() {
+        Err(SpatializerError::NotFound)
+    } else {
+        Ok(spatializers)
+    }
+}
+
+/// FMOD reports this until the event instance has created its channel group. Other errors,
+/// e.g. for a released instance, do not go away by waiting.
+fn is_not_loaded(error: &libfmod::Error) -> bool {
+    matches!(
+        error,
+        libfmod::Error::Fmod { code, .. } if *code == libfmod::ffi::FMOD_ERR_STUDIO_NOT_LOADED
+    )
+}
+
+fn collect_spatializers(
+    channel_group: ChannelGroup,
+    spatializers: &mut Vec<Dsp>,
+) -> Result<(), SpatializerError> {
+    for index_dsp in 0..channel_group.get_num_ds_ps()? {
+        let dsp = channel_group.get_dsp(index_dsp)?;
+        // See issue #3, this seems to cause issues when Steam Audio is configured
+        // for reflection simulations.
+        let dsp_info = dsp.get_info()?;
+
+        if dsp_info.0 == SPATIALIZER_NAME {
+            spatializers.push(dsp);
+        }
+    }
+
+    for index_group in 0..channel_group.get_num_groups()? {
+        let group = channel_group.get_group(index_group)?;
+        collect_spatializers(group, spatializers)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,414 @@
+use crate::error::PhononError;
+use crate::phonon_source::{PhononSource, PhononSources};
+use crate::spatializer::SpatializerError;
+use bevy::prelude::*;
+use libfmod::Dsp;
+use serde::{Deserialize, Serialize};
+
+/// Indices of the parameters of the Steam Audio Spatializer DSP.
+/// These match the `Params` enum in the Steam Audio FMOD plugin (spatialize_effect.cpp).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum SpatializerParam {
+    ApplyDistanceAttenuation = 0,
+    ApplyAirAbsorption = 1,
+    ApplyDirectivity = 2,
+    ApplyOcclusion = 3,
+    ApplyTransmission = 4,
+    ApplyReflections = 5,
+    ApplyPathing = 6,
+    HrtfInterpolation = 7,
+    DistanceAttenuation = 8,
+    DistanceAttenuationRolloffType = 9,
+    DistanceAttenuationMinDistance = 10,
+    DistanceAttenuationMaxDistance = 11,
+    AirAbsorptionLow = 12,
+    AirAbsorptionMid = 13,
+    AirAbsorptionHigh = 14,
+    Directivity = 15,
+    DirectivityDipoleWeight = 16,
+    DirectivityDipolePower = 17,
+    Occlusion = 18,
+    TransmissionType = 19,
+    TransmissionLow = 20,
+    TransmissionMid = 21,
+    TransmissionHigh = 22,
+    DirectMixLevel = 23,
+    ReflectionsBinaural = 24,
+    ReflectionsMixLevel = 25,
+    PathingBinaural = 26,
+    PathingMixLevel = 27,
+    SourcePosition = 28,
+    OverallGain = 29,
+    OutputFormat = 30,
+    DirectBinaural = 31,
+    DistanceAttenuationRange = 32,
+    /// Handle of the Steam Audio source, as returned by `steamaudio::fmod::add_source`.
+    /// The plugin reads the simulation results of that source through it.
+    SimulationOutputs = 33,
+}
+
+impl SpatializerParam {
+    pub fn index(self) -> i32 {
+        self as i32
+    }
+}
+
+/// Where the spatializer gets the value of an effect from.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[reflect(Default)]
+#[repr(i32)]
+pub enum ApplyType {
+    Disabled = 0,
+    /// Uses the results of the Steam Audio simulation.
+    #[default]
+    SimulationDefined = 1,
+    /// Uses the value of the corresponding parameter, e.g. `SpatializerParam::Occlusion`.
+    UserDefined = 2,
+}
+
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[reflect(Default)]
+#[repr(i32)]
+pub enum HrtfInterpolation {
+    #[default]
+    Nearest = 0,
+    Bilinear = 1,
+}
+
+impl TryFrom<i32> for ApplyType {
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ApplyType::Disabled),
+            1 => Ok(ApplyType::SimulationDefined),
+            2 => Ok(ApplyType::UserDefined),
+            value => Err(value),
+        }
+    }
+}
+
+impl TryFrom<i32> for HrtfInterpolation {
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(HrtfInterpolation::Nearest),
+            1 => Ok(HrtfInterpolation::Bilinear),
+            value => Err(value),
+        }
+    }
+}
+
+/// Typed access to the parameters of a Steam Audio Spatializer DSP.
+#[derive(Debug, Clone, Copy)]
+pub struct Spatializer(pub Dsp);
+
+impl Spatializer {
+    fn set_apply(&self, param: SpatializerParam, value: ApplyType) -> Result<(), libfmod::Error> {
+        self.0.set_parameter_int(param.index(), value as i32)
+    }
+
+    fn get_apply(&self, param: SpatializerParam) -> Result<ApplyType, SpatializerError> {
+        let (value, _) = self.0.get_parameter_int(param.index(), 0)?;
+        ApplyType::try_from(value).map_err(SpatializerError::UnexpectedValue)
+    }
+
+    fn set_bool(&self, param: SpatializerParam, value: bool) -> Result<(), libfmod::Error> {
+        self.0.set_parameter_bool(param.index(), value)
+    }
+
+    fn get_bool(&self, param: SpatializerParam) -> Result<bool, libfmod::Error> {
+        let (value, _) = self.0.get_parameter_bool(param.index(), 0)?;
+        Ok(value)
+    }
+
+    fn set_float(&self, param: SpatializerParam, value: f32) -> Result<(), libfmod::Error> {
+        self.0.set_parameter_float(param.index(), value)
+    }
+
+    fn get_float(&self, param: SpatializerParam) -> Result<f32, libfmod::Error> {
+        let (value, _) = self.0.get_parameter_float(param.index(), 0)?;
+        Ok(value)
+    }
+
+    pub fn set_apply_distance_attenuation(&self, value: ApplyType) -> Result<(), libfmod::Error> {
+        self.set_apply(SpatializerParam::ApplyDistanceAttenuation, value)
+    }
+
+    pub fn apply_distance_attenuation(&self) -> Result<ApplyType, SpatializerError> {
+        self.get_apply(SpatializerParam::ApplyDistanceAttenuation)
+    }
+
+    pub fn set_apply_air_absorption(&self, value: ApplyType) -> Result<(), libfmod::Error> {
+        self.set_apply(SpatializerParam::ApplyAirAbsorption, value)
+    }
+
+    pub fn apply_air_absorption(&self) -> Result<ApplyType, SpatializerError> {
+        self.get_apply(SpatializerParam::ApplyAirAbsorption)
+    }
+
+    pub fn set_apply_directivity(&self, value: ApplyType) -> Result<(), libfmod::Error> {
+        self.set_apply(SpatializerParam::ApplyDirectivity, value)
+    }
+
+    pub fn apply_directivity(&self) -> Result<ApplyType, SpatializerError> {
+        self.get_apply(SpatializerParam::ApplyDirectivity)
+    }
+
+    pub fn set_apply_occlusion(&self, value: ApplyType) -> Result<(), libfmod::Error> {
+        self.set_apply(SpatializerParam::ApplyOcclusion, value)
+    }
+
+    pub fn apply_occlusion(&self) -> Result<ApplyType, SpatializerError> {
+        self.get_apply(SpatializerParam::ApplyOcclusion)
+    }
+
+    pub fn set_apply_transmission(&self, value: ApplyType) -> Result<(), libfmod::Error> {
+        self.set_apply(SpatializerParam::ApplyTransmission, value)
+    }
+
+    pub fn apply_transmission(&self) -> Result<ApplyType, SpatializerError> {
+        self.get_apply(SpatializerParam::ApplyTransmission)
+    }
+
+    pub fn set_apply_reflections(&self, value: bool) -> Result<(), libfmod::Error> {
+        self.set_bool(SpatializerParam::ApplyReflections, value)
+    }
+
+    pub fn apply_reflections(&self) -> Result<bool, libfmod::Error> {
+        self.get_bool(SpatializerParam::ApplyReflections)
+    }
+
+    pub fn set_apply_pathing(&self, value: bool) -> Result<(), libfmod::Error> {
+        self.set_bool(SpatializerParam::ApplyPathing, value)
+    }
+
+    pub fn apply_pathing(&self) -> Result<bool, libfmod::Error> {
+        self.get_bool(SpatializerParam::ApplyPathing)
+    }
+
+    pub fn set_hrtf_interpolation(&self, value: HrtfInterpolation) -> Result<(), libfmod::Error> {
+        self.0
+            .set_parameter_int(SpatializerParam::HrtfInterpolation.index(), value as i32)
+    }
+
+    pub fn hrtf_interpolation(&self) -> Result<HrtfInterpolation, SpatializerError> {
+        let (value, _) = self
+            .0
+            .get_parameter_int(SpatializerParam::HrtfInterpolation.index(), 0)?;
+        HrtfInterpolation::try_from(value).map_err(SpatializerError::UnexpectedValue)
+    }
+
+    pub fn set_direct_binaural(&self, value: bool) -> Result<(), libfmod::Error> {
+        self.set_bool(SpatializerParam::DirectBinaural, value)
+    }
+
+    pub fn direct_binaural(&self) -> Result<bool, libfmod::Error> {
+        self.get_bool(SpatializerParam::DirectBinaural)
+    }
+
+    pub fn set_reflections_binaural(&self, value: bool) -> Result<(), libfmod::Error> {
+        self.set_bool(SpatializerParam::ReflectionsBinaural, value)
+    }
+
+    pub fn reflections_binaural(&self) -> Result<bool, libfmod::Error> {
+        self.get_bool(SpatializerParam::ReflectionsBinaural)
+    }
+
+    pub fn set_pathing_binaural(&self, value: bool) -> Result<(), libfmod::Error> {
+        self.set_bool(SpatializerParam::PathingBinaural, value)
+    }
+
+    pub fn pathing_binaural(&self) -> Result<bool, libfmod::Error> {
+        self.get_bool(SpatializerParam::PathingBinaural)
+    }
+
+    pub fn set_direct_mix_level(&self, value: f32) -> Result<(), libfmod::Error> {
+        self.set_float(SpatializerParam::DirectMixLevel, value)
+    }
+
+    pub fn direct_mix_level(&self) -> Result<f32, libfmod::Error> {
+        self.get_float(SpatializerParam::DirectMixLevel)
+    }
+
+    pub fn set_reflections_mix_level(&self, value: f32) -> Result<(), libfmod::Error> {
+        self.set_float(SpatializerParam::ReflectionsMixLevel, value)
+    }
+
+    pub fn reflections_mix_level(&self) -> Result<f32, libfmod::Error> {
+        self.get_float(SpatializerParam::ReflectionsMixLevel)
+    }
+
+    pub fn set_pathing_mix_level(&self, value: f32) -> Result<(), libfmod::Error> {
+        self.set_float(SpatializerParam::PathingMixLevel, value)
+    }
+
+    pub fn pathing_mix_level(&self) -> Result<f32, libfmod::Error> {
+        self.get_float(SpatializerParam::PathingMixLevel)
+    }
+
+    /// By setting this the Steam Audio FMOD plugin can retrieve the
+    /// simulation results like occlusion and reflection. -1 disconnects the source.
+    pub fn set_simulation_outputs(&self, source_address: i32) -> Result<(), libfmod::Error> {
+        self.0
+            .set_parameter_int(SpatializerParam::SimulationOutputs.index(), source_address)
+    }
+
+    pub fn simulation_outputs(&self) -> Result<i32, libfmod::Error> {
+        let (value, _) = self
+            .0
+            .get_parameter_int(SpatializerParam::SimulationOutputs.index(), 0)?;
+        Ok(value)
+    }
+
+    /// Writes all the values of `params` to the DSP.
+    pub fn apply(&self, params: &SpatializerParams) -> Result<(), libfmod::Error> {
+        self.set_apply_distance_attenuation(params.apply_distance_attenuation)?;
+        self.set_apply_air_absorption(params.apply_air_absorption)?;
+        self.set_apply_directivity(params.apply_directivity)?;
+        self.set_apply_occlusion(params.apply_occlusion)?;
+        self.set_apply_transmission(params.apply_transmission)?;
+        self.set_apply_reflections(params.apply_reflections)?;
+        self.set_apply_pathing(params.apply_pathing)?;
+        self.set_hrtf_interpolation(params.hrtf_interpolation)?;
+        self.set_direct_binaural(params.direct_binaural)?;
+        self.set_reflections_binaural(params.reflections_binaural)?;
+        self.set_pathing_binaural(params.pathing_binaural)?;
+        self.set_direct_mix_level(params.direct_mix_level)?;
+        self.set_reflections_mix_level(params.reflections_mix_level)?;
+        self.set_pathing_mix_level(params.pathing_mix_level)
+    }
+}
+
+impl From<Dsp> for Spatializer {
+    fn from(dsp: Dsp) -> Self {
+        Spatializer(dsp)
+    }
+}
+
+/// Mirrors the parameters of the Steam Audio Spatializer DSPs of an audio source.
+/// When this component changes the values are written to the DSPs, overriding whatever
+/// was set in FMOD Studio. Without it the FMOD Studio values are used.
+#[derive(Component, Reflect, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[reflect(Component, Default)]
+pub struct SpatializerParams {
+    pub apply_distance_attenuation: ApplyType,
+    pub apply_air_absorption: ApplyType,
+    pub apply_directivity: ApplyType,
+    pub apply_occlusion: ApplyType,
+    pub apply_transmission: ApplyType,
+    pub apply_reflections: bool,
+    pub apply_pathing: bool,
+    pub hrtf_interpolation: HrtfInterpolation,
+    pub direct_binaural: bool,
+    pub reflections_binaural: bool,
+    pub pathing_binaural: bool,
+    pub direct_mix_level: f32,
+    pub reflections_mix_level: f32,
+    pub pathing_mix_level: f32,
+}
+
+impl Default for SpatializerParams {
+    fn default() -> Self {
+        Self {
+            apply_distance_attenuation: ApplyType::SimulationDefined,
+            apply_air_absorption: ApplyType::SimulationDefined,
+            apply_directivity: ApplyType::Disabled,
+            apply_occlusion: ApplyType::SimulationDefined,
+            apply_transmission: ApplyType::SimulationDefined,
+            apply_reflections: true,
+            apply_pathing: false,
+            hrtf_interpolation: HrtfInterpolation::Nearest,
+            direct_binaural: true,
+            reflections_binaural: false,
+            pathing_binaural: false,
+            direct_mix_level: 1.0,
+            reflections_mix_level: 1.0,
+            pathing_mix_level: 1.0,
+        }
+    }
+}
+
+/// Pushes `SpatializerParams` into the DSPs when they change or when the source
+/// has just been registered. Failures are sent as `PhononError::Spatializer`.
+pub(crate) fn update_spatializer_params(
+    sources: Res<PhononSources>,
+    params_query: Query<
+        (Entity, &SpatializerParams),
+        Or<(Changed<SpatializerParams>, Added<PhononSource>)>,
+    >,
+    mut errors: EventWriter<PhononError>,
+) {
+    for (entity, params) in &params_query {
+        let Some(fmod_source) = sources
+            .get(&entity)
+            .and_then(|registered| registered.fmod.as_ref())
+        else {
+            continue;
+        };
+
+        for dsp in &fmod_source.dsps {
+            if let Err(error) = Spatializer(*dsp).apply(params) {
+                errors.send(PhononError::Spatializer {
+                    entity,
+                    error: error.into(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn param_indices_match_the_plugin() {
+        assert_eq!(SpatializerParam::ApplyDistanceAttenuation.index(), 0);
+        assert_eq!(SpatializerParam::ApplyPathing.index(), 6);
+        assert_eq!(SpatializerParam::HrtfInterpolation.index(), 7);
+        assert_eq!(SpatializerParam::Occlusion.index(), 18);
+        assert_eq!(SpatializerParam::DirectMixLevel.index(), 23);
+        assert_eq!(SpatializerParam::PathingMixLevel.index(), 27);
+        assert_eq!(SpatializerParam::DirectBinaural.index(), 31);
+        assert_eq!(SpatializerParam::SimulationOutputs.index(), 33);
+    }
+
+    #[test]
+    fn apply_types_round_trip() {
+        for apply_type in [
+            ApplyType::Disabled,
+            ApplyType::SimulationDefined,
+            ApplyType::UserDefined,
+        ] {
+            assert_eq!(ApplyType::try_from(apply_type as i32), Ok(apply_type));
+        }
+
+        assert_eq!(ApplyType::try_from(3), Err(3));
+        assert_eq!(ApplyType::try_from(-1), Err(-1));
+    }
+
+    #[test]
+    fn hrtf_interpolations_round_trip() {
+        for interpolation in [HrtfInterpolation::Nearest, HrtfInterpolation::Bilinear] {
+            assert_eq!(
+                HrtfInterpolation::try_from(interpolation as i32),
+                Ok(interpolation)
+            );
+        }
+
+        assert_eq!(HrtfInterpolation::try_from(2), Err(2));
+    }
+
+    #[test]
+    fn defaults_use_the_simulation() {
+        let params = SpatializerParams::default();
+
+        assert_eq!(params.apply_occlusion, ApplyType::SimulationDefined);
+        assert_eq!(params.apply_transmission, ApplyType::SimulationDefined);
+        assert_eq!(params.apply_directivity, ApplyType::Disabled);
+        assert_eq!(params.hrtf_interpolation, HrtfInterpolation::Nearest);
+    }
+}